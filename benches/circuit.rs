@@ -0,0 +1,91 @@
+//! Throughput benchmarks for parsing and evaluating circuits, run with `cargo bench`.
+//! Every benchmark reports the mean time of a fixed number of iterations.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use mpc_in_rust::circuit::{Circuit, CircuitBuilder, CircuitFormat};
+
+/// Allocator counting the number of allocations, s.t. the benchmarks can report them
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Runs the function the given number of times and prints the mean duration and allocations of one run
+fn bench<T>(name: &str, iterations: u32, mut f: impl FnMut() -> T) {
+    // warm up caches and the allocator before measuring
+    black_box(f());
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(f());
+    }
+    let mean = start.elapsed() / iterations;
+    let mean_allocations = (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / iterations as usize;
+    println!("{name:<32} {:>12.3?} per iteration, {mean_allocations:>8} allocations", mean.max(Duration::from_nanos(1)));
+}
+
+/// Generates a circuit with two 64-bit inputs and layers of XORs and ANDs mixing neighbouring wires,
+/// which is the same on every run
+fn generated_circuit(num_layers: u32) -> Circuit {
+    let mut builder = CircuitBuilder::new();
+    let mut wires = builder.input_port(64);
+    wires.extend(builder.input_port(64));
+    for layer in 0..num_layers {
+        wires = (0..wires.len())
+            .map(|i| {
+                let (x, y) = (wires[i], wires[(i + 1 + layer as usize) % wires.len()]);
+                let out = builder.new_wire();
+                if i % 2 == 0 { builder.add_xor(x, y, out) } else { builder.add_and(x, y, out) };
+                out
+            })
+            .collect();
+    }
+    builder.output_port_from(&wires[..64]);
+    builder.build().expect("generated circuit is valid")
+}
+
+fn main() {
+    let small = generated_circuit(16);
+    let small_bristol = small.to_bristol();
+    let aes_bristol = std::fs::read_to_string("res/aes_128.txt").expect("AES circuit exists");
+    let aes = Circuit::parse_with_format(&aes_bristol, CircuitFormat::Fashion).expect("AES circuit is valid");
+
+    bench("parse/small", 1000, || Circuit::parse_with_format(&small_bristol, CircuitFormat::Fashion).unwrap());
+    bench("parse/aes_128", 20, || Circuit::parse_with_format(&aes_bristol, CircuitFormat::Fashion).unwrap());
+
+    let small_inputs = vec![vec![true; 64], vec![false; 64]];
+    let aes_inputs = vec![vec![true; 128], vec![false; 128]];
+    bench("evaluate/small", 1000, || small.evaluate(&small_inputs));
+    bench("evaluate/aes_128", 100, || aes.evaluate(&aes_inputs));
+
+    // the packed evaluator processes 64 sets of inputs per run
+    let small_packed = vec![vec![u64::MAX; 64], vec![0x5555_5555_5555_5555; 64]];
+    let aes_packed = vec![vec![u64::MAX; 128], vec![0x5555_5555_5555_5555; 128]];
+    bench("evaluate_packed/small", 1000, || small.evaluate_packed(&small_packed));
+    bench("evaluate_packed/aes_128", 100, || aes.evaluate_packed(&aes_packed));
+
+    // a wide circuit, whose layers are large enough to be split across threads
+    let wide = generated_circuit(8);
+    let wide = (0..63).fold(wide.clone(), |stacked, _| stacked.parallel(&wide).unwrap());
+    let wide_inputs = vec![vec![true; 64]; 128];
+    bench("evaluate/wide", 100, || wide.evaluate(&wide_inputs));
+    bench("evaluate_parallel/wide", 100, || wide.evaluate_parallel(&wide_inputs));
+}
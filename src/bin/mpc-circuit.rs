@@ -0,0 +1,59 @@
+//! Prints a summary of a bristol fashion circuit, or the circuit as a Graphviz DOT digraph with `--dot`.
+//!
+//! Usage: `mpc-circuit [--dot] <circuit file>`
+
+use std::env;
+use std::process::ExitCode;
+
+use mpc_in_rust::circuit::{Circuit, CircuitFormat};
+
+const USAGE: &str = "Usage: mpc-circuit [--dot] <circuit file>";
+
+fn main() -> ExitCode {
+    let mut dot = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--dot" => dot = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+
+    let circuit = match Circuit::from_file_with_format(&path, CircuitFormat::Fashion) {
+        Ok(circuit) => circuit,
+        Err(error) => {
+            eprintln!("Could not parse {path}: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if dot {
+        print!("{}", circuit.to_dot());
+        return ExitCode::SUCCESS;
+    }
+
+    let header = circuit.header();
+    let counts = circuit.gate_counts();
+    let join = |widths: &[u32]| widths.iter().map(u32::to_string).collect::<Vec<_>>().join(" ");
+    println!("gates: {} (XOR: {}, AND: {}, INV: {}, MAND: {})", circuit.gates().len(), counts.xor, counts.and, counts.inv, counts.mand);
+    println!("wires: {}", header.num_wires());
+    match circuit.has_cycle() {
+        false => println!("AND-depth: {}", circuit.and_depth()),
+        true => println!("AND-depth: undefined, the circuit contains a cycle"),
+    }
+    println!("input ports: {} ({})", header.input_wires().len(), join(header.input_wires()));
+    println!("output ports: {} ({})", header.output_wires().len(), join(header.output_wires()));
+    ExitCode::SUCCESS
+}
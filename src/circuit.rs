@@ -1,47 +1,542 @@
+use alloc::collections::btree_map::Entry;
+use alloc::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::fmt::{self, Display, Formatter, Write};
+use core::hash::{Hash, Hasher};
+use core::iter::{Skip, Take};
+use core::mem;
+use core::ops::{Add, AddAssign, BitAnd, BitXor, Index, Not, Range, Sub};
+use core::str::SplitWhitespace;
+#[cfg(feature = "std")]
+use std::collections::hash_map::RandomState;
+#[cfg(feature = "std")]
+use std::fs::read_to_string;
+#[cfg(feature = "std")]
+use std::hash::BuildHasher;
+#[cfg(feature = "std")]
+use std::io::{self, BufRead};
+#[cfg(feature = "std")]
+use std::path::Path;
+#[cfg(feature = "std")]
+use std::thread;
+#[cfg(feature = "std")]
 use thiserror::Error;
-use ParserError::*;
+use ParseError::*;
 
-#[derive(Error, Debug)]
-pub enum ParserError {
-    #[error("Invalid entry at line {line}: {description:?}")]
+#[cfg_attr(feature = "std", derive(Error))]
+#[derive(Debug)]
+pub enum ParseError {
     InvalidEntry { line: u32, description: String },
 
-    #[error("Syntax error at line {line}: {description:?}")]
     SyntaxError { line: u32, description: String },
 
-    #[error("Unexpected token at line {line}: expected {expected:?} but got {got:?}")]
-    UnexpectedToken { line: u32, expected: TokenType, got: String },
+    UnexpectedToken { line: u32, column: u32, expected: TokenType, got: String },
+
+    MalformedHeader { line: u32, description: String },
+
+    UnknownGate { line: u32, column: u32, got: String },
+
+    BadWireIndex { line: u32, column: u32, got: String },
+
+    InvalidNumber { line: u32, column: u32, got: String },
+
+    WrongArity { gate: &'static str, expected: (usize, usize), found: (usize, usize), line: u32 },
+
+    NonAscii { line: u32, column: u32 },
+
+    LimitExceeded { line: u32, resource: &'static str, limit: u32, found: u64 },
+
+    TooFewLines { expected: usize, got: usize },
+
+    #[cfg(feature = "std")]
+    Io(#[from] io::Error),
+
+    Invalid(#[cfg_attr(feature = "std", source)] ValidationError),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidEntry { line, description } => write!(f, "Invalid entry at line {line}: {description:?}"),
+            SyntaxError { line, description } => write!(f, "Syntax error at line {line}: {description:?}"),
+            UnexpectedToken { line, column, expected, got } =>
+                write!(f, "Unexpected token at line {line}, column {column}: expected {expected} but got {got:?}"),
+            MalformedHeader { line, description } => write!(f, "Malformed header at line {line}: {description:?}"),
+            UnknownGate { line, column, got } => write!(f, "Unknown gate type at line {line}, column {column}: {got:?}"),
+            BadWireIndex { line, column, got } =>
+                write!(f, "Bad wire index at line {line}, column {column}: expected {}, found {got:?}", TokenType::WireIndex),
+            InvalidNumber { line, column, got } =>
+                write!(f, "Invalid number at line {line}, column {column}: expected a {} below 2^32, found {got:?}", TokenType::NonNegativeNumber),
+            WrongArity { gate, expected, found, line } => write!(
+                f,
+                "Wrong arity at line {line}: {gate} gates have {} input and {} output wires, but the line declares {} and {}",
+                expected.0, expected.1, found.0, found.1,
+            ),
+            NonAscii { line, column } => write!(f, "Non-ASCII byte at line {line}, column {column}: bristol files are plain ASCII"),
+            LimitExceeded { line, resource, limit, found } =>
+                write!(f, "Limit exceeded at line {line}: the circuit has at least {found} {resource}, but at most {limit} are allowed"),
+            TooFewLines { expected, got } => write!(f, "Too few lines: expected at least {expected} non-empty lines but got {got}"),
+            #[cfg(feature = "std")]
+            Io(error) => write!(f, "Could not read circuit: {error}"),
+            Invalid(error) => write!(f, "Invalid circuit: {error}"),
+        }
+    }
+}
+
+impl From<ValidationError> for ParseError {
+    fn from(error: ValidationError) -> Self {
+        Invalid(error)
+    }
+}
+
+/// The error type was called `ParserError` before, which is kept as an alias for existing code
+#[deprecated(note = "renamed to `ParseError`")]
+pub type ParserError = ParseError;
+
+#[cfg_attr(feature = "std", derive(Error))]
+#[derive(Debug, PartialEq)]
+pub enum ValidationError {
+    GateCountMismatch { declared: u32, actual: usize },
+
+    WireOutOfRange { gate: usize, wire: WireId, num_wires: u32 },
+
+    DuplicateOutput { wire: WireId, first_gate: usize, second_gate: usize },
+
+    InputWireAssigned { gate: usize, wire: WireId },
+
+    UndrivenWire(WireId),
+
+    WireCountMismatch { declared: u32, computed: u32 },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::GateCountMismatch { declared, actual } =>
+                write!(f, "Header declares {declared} gates but the circuit contains {actual} gates"),
+            ValidationError::WireOutOfRange { gate, wire, num_wires } =>
+                write!(f, "Gate {gate} references wire {wire}, but the circuit only has {num_wires} wires"),
+            ValidationError::DuplicateOutput { wire, first_gate, second_gate } =>
+                write!(f, "Gates {first_gate} and {second_gate} both assign wire {wire}"),
+            ValidationError::InputWireAssigned { gate, wire } =>
+                write!(f, "Gate {gate} assigns wire {wire}, which belongs to an input port"),
+            ValidationError::UndrivenWire(wire) =>
+                write!(f, "Wire {wire} is read by a gate, but neither belongs to an input port nor is assigned by any gate"),
+            ValidationError::WireCountMismatch { declared, computed } =>
+                write!(f, "Header declares {declared} wires but the circuit references only {computed} wires"),
+        }
+    }
+}
+
+#[cfg_attr(feature = "std", derive(Error))]
+#[derive(Debug, PartialEq)]
+pub struct CycleError {
+    // index of a gate that (transitively) depends on its own output
+    pub gate: usize,
+}
+
+impl Display for CycleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "The circuit contains a cycle involving gate {}", self.gate)
+    }
+}
+
+#[cfg_attr(feature = "std", derive(Error))]
+#[derive(Debug, PartialEq)]
+pub enum ComposeError {
+    WidthMismatch { outputs: u32, inputs: u32 },
+
+    WireOverflow(WireOverflow),
+}
+
+impl Display for ComposeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ComposeError::WidthMismatch { outputs, inputs } =>
+                write!(f, "The first circuit has {outputs} output wires, but the second circuit has {inputs} input wires"),
+            ComposeError::WireOverflow(error) => error.fmt(f),
+        }
+    }
+}
+
+impl From<WireOverflow> for ComposeError {
+    fn from(error: WireOverflow) -> Self {
+        ComposeError::WireOverflow(error)
+    }
+}
+
+#[cfg_attr(feature = "std", derive(Error))]
+#[derive(Debug, PartialEq)]
+pub struct WireOverflow {
+    pub required: u64,
+}
+
+impl Display for WireOverflow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "The circuit would need {} wires, but at most {} wires can be indexed", self.required, u32::MAX)
+    }
+}
+
+#[cfg_attr(feature = "std", derive(Error))]
+#[derive(Debug, PartialEq)]
+pub struct ExpressionTooLarge {
+    pub wire: WireId,
+}
+
+impl Display for ExpressionTooLarge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "The expression of wire {} exceeds the limit of {MAX_EXPRESSION_LEN} characters", self.wire)
+    }
+}
+
+/// Maximum length of the expressions built by `output_expressions`, which grow exponentially with
+/// the depth of circuits reusing wires
+pub const MAX_EXPRESSION_LEN: usize = 4096;
+
+#[cfg_attr(feature = "std", derive(Error))]
+#[derive(Debug, PartialEq)]
+pub struct TooManyInputs {
+    pub inputs: usize,
+}
+
+impl Display for TooManyInputs {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "The circuit has {} input wires, but truth tables are limited to {MAX_TRUTH_TABLE_INPUTS} inputs", self.inputs)
+    }
+}
+
+/// Maximum number of input wires of circuits whose truth table can be computed, which bounds the
+/// table to about a million rows
+pub const MAX_TRUTH_TABLE_INPUTS: usize = 20;
+
+/// Rows of a truth table, each holding all input bits and the resulting output bits of a circuit
+pub type TruthTable = Vec<(Vec<bool>, Vec<bool>)>;
+
+#[cfg_attr(feature = "std", derive(Error))]
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+    // all input bits of the circuit, in the order of the input ports
+    pub inputs: Vec<bool>,
+    pub expected: Vec<bool>,
+    pub got: Vec<bool>,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "The circuit computes {:?} on the inputs {:?}, but {:?} was expected", self.got, self.inputs, self.expected)
+    }
+}
+
+#[cfg_attr(feature = "std", derive(Error))]
+#[derive(Debug, PartialEq)]
+pub enum SpecializeError {
+    NoSuchPort { port: usize, num_ports: usize },
+
+    WidthMismatch { port: usize, width: usize, bits: usize },
+}
+
+impl Display for SpecializeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SpecializeError::NoSuchPort { port, num_ports } =>
+                write!(f, "Input port {port} does not exist, as the circuit only has {num_ports} input ports"),
+            SpecializeError::WidthMismatch { port, width, bits } =>
+                write!(f, "Input port {port} has {width} wires, but {bits} bits are fixed"),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum TokenType {
     NonNegativeNumber,
     GateIdentifier,
+    WireIndex,
+}
+
+impl Display for TokenType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenType::NonNegativeNumber => write!(f, "non-negative number"),
+            TokenType::GateIdentifier => write!(f, "gate identifier"),
+            TokenType::WireIndex => write!(f, "integer wire index"),
+        }
+    }
 }
 
+/// Index of a wire in a circuit. Wires are numbered from 0 like in bristol files, but the newtype
+/// keeps them apart from counts like the number of gates or wires of a circuit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct WireId(pub u32);
 
-#[derive(Debug, PartialEq)]
+impl WireId {
+    /// Returns the wire as an index into a slice holding one entry per wire
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    /// Moves the wire up by a number of wires, or returns None if the wire can not be indexed by a u32
+    pub fn checked_add(self, offset: u32) -> Option<WireId> {
+        self.0.checked_add(offset).map(WireId)
+    }
+
+    /// Moves the wire down by a number of wires, or returns None if the wire would be below wire 0
+    pub fn checked_sub(self, offset: u32) -> Option<WireId> {
+        self.0.checked_sub(offset).map(WireId)
+    }
+}
+
+impl From<u32> for WireId {
+    fn from(wire: u32) -> Self {
+        WireId(wire)
+    }
+}
+
+impl From<WireId> for u32 {
+    fn from(wire: WireId) -> Self {
+        wire.0
+    }
+}
+
+/// Moves the wire up by a number of wires. Like the arithmetic of u32, this panics on overflow in
+/// debug builds and wraps in release builds, see `checked_add` for wires that may overflow.
+impl Add<u32> for WireId {
+    type Output = WireId;
+
+    fn add(self, offset: u32) -> WireId {
+        WireId(self.0 + offset)
+    }
+}
+
+impl AddAssign<u32> for WireId {
+    fn add_assign(&mut self, offset: u32) {
+        self.0 += offset;
+    }
+}
+
+/// Moves the wire down by a number of wires. Like the arithmetic of u32, this panics on underflow in
+/// debug builds and wraps in release builds, see `checked_sub` for wires that may underflow.
+impl Sub<u32> for WireId {
+    type Output = WireId;
+
+    fn sub(self, offset: u32) -> WireId {
+        WireId(self.0 - offset)
+    }
+}
+
+/// Returns the number of wires between two wires, which panics or wraps like `Sub<u32>` if the
+/// other wire is the higher one
+impl Sub for WireId {
+    type Output = u32;
+
+    fn sub(self, other: WireId) -> u32 {
+        self.0 - other.0
+    }
+}
+
+impl Display for WireId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A gate of a bristol circuit.
+///
+/// New gate types may be added in the future, so code outside of this crate matching on a gate
+/// needs a wildcard arm, e.g. `_ => unimplemented!("unsupported gate {gate}")`. Existing matches
+/// listing every gate type only need this additional arm to keep compiling.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
 pub enum Gate {
-    // Currently, EQ, EQW, and MAND gates are not yet implemented
     // Each gate has one field for each input and each output, denoting the wire connected to the port, respectively
     XOR {
-        input_a: u32,
-        input_b: u32,
-        output: u32,
+        input_a: WireId,
+        input_b: WireId,
+        output: WireId,
     },
     AND {
-        input_a: u32,
-        input_b: u32,
-        output: u32,
+        input_a: WireId,
+        input_b: WireId,
+        output: WireId,
     },
     INV {
-        input: u32,
-        output: u32,
+        input: WireId,
+        output: WireId,
+    },
+    // EQ gates have no input wire, the input field of the gate line holds the constant assigned to the output wire
+    EQ {
+        constant: bool,
+        output: WireId,
+    },
+    EQW {
+        input: WireId,
+        output: WireId,
+    },
+    // MAND gates compute n ANDs in parallel, the i-th output being the AND of the i-th and the (n+i)-th input
+    MAND {
+        inputs: Vec<WireId>,
+        outputs: Vec<WireId>,
     },
 }
 
+impl Gate {
+    /// Returns the bristol identifier of the gate type, e.g. `XOR`
+    fn type_name(&self) -> &'static str {
+        match self {
+            Gate::XOR { .. } => "XOR",
+            Gate::AND { .. } => "AND",
+            Gate::INV { .. } => "INV",
+            Gate::EQ { .. } => "EQ",
+            Gate::EQW { .. } => "EQW",
+            Gate::MAND { .. } => "MAND",
+        }
+    }
+
+    /// Returns whether the gate is an AND or MAND, which are the only gates requiring interaction in MPC
+    fn is_nonlinear(&self) -> bool {
+        matches!(self, Gate::AND { .. } | Gate::MAND { .. })
+    }
 
-#[derive(Debug, PartialEq)]
+    /// Returns the wires read by this gate, e.g. both inputs of an AND and none of an EQ
+    pub fn inputs(&self) -> Vec<WireId> {
+        match self {
+            Gate::XOR { input_a, input_b, .. } | Gate::AND { input_a, input_b, .. } => vec![*input_a, *input_b],
+            Gate::INV { input, .. } | Gate::EQW { input, .. } => vec![*input],
+            Gate::EQ { .. } => vec![],
+            Gate::MAND { inputs, .. } => inputs.clone(),
+        }
+    }
+
+    /// Replaces every wire read by this gate with the result of the given function
+    fn map_inputs(&mut self, mut f: impl FnMut(WireId) -> WireId) {
+        match self {
+            Gate::XOR { input_a, input_b, .. } | Gate::AND { input_a, input_b, .. } => {
+                *input_a = f(*input_a);
+                *input_b = f(*input_b);
+            }
+            Gate::INV { input, .. } | Gate::EQW { input, .. } => *input = f(*input),
+            Gate::EQ { .. } => {}
+            Gate::MAND { inputs, .. } => inputs.iter_mut().for_each(|input| *input = f(*input)),
+        }
+    }
+
+    /// Replaces every wire written by this gate with the result of the given function
+    fn map_outputs(&mut self, mut f: impl FnMut(WireId) -> WireId) {
+        match self {
+            Gate::XOR { output, .. } | Gate::AND { output, .. } | Gate::INV { output, .. }
+            | Gate::EQ { output, .. } | Gate::EQW { output, .. } => *output = f(*output),
+            Gate::MAND { outputs, .. } => outputs.iter_mut().for_each(|output| *output = f(*output)),
+        }
+    }
+
+    /// Returns the gate with every wire it reads or writes replaced by the result of the given function
+    fn mapped(mut self, f: impl Fn(WireId) -> WireId) -> Gate {
+        self.map_inputs(&f);
+        self.map_outputs(&f);
+        self
+    }
+
+    /// Returns the wires written by this gate
+    pub fn outputs(&self) -> Vec<WireId> {
+        match self {
+            Gate::XOR { output, .. } | Gate::AND { output, .. } | Gate::INV { output, .. }
+            | Gate::EQ { output, .. } | Gate::EQW { output, .. } => vec![*output],
+            Gate::MAND { outputs, .. } => outputs.clone(),
+        }
+    }
+}
+
+impl Gate {
+    /// Returns the highest wire read or assigned by the gate, without collecting its wires like
+    /// `inputs` and `outputs`. Only MAND gates without any wires have none.
+    fn max_wire(&self) -> Option<WireId> {
+        match self {
+            Gate::XOR { input_a, input_b, output } | Gate::AND { input_a, input_b, output } =>
+                Some(*input_a.max(input_b).max(output)),
+            Gate::INV { input, output } | Gate::EQW { input, output } => Some(*input.max(output)),
+            Gate::EQ { output, .. } => Some(*output),
+            Gate::MAND { inputs, outputs } => inputs.iter().chain(outputs).max().copied(),
+        }
+    }
+
+    /// Evaluates the gate on the given wire values and appends each output wire with its value
+    #[cfg(feature = "std")]
+    fn evaluate_into(&self, wires: &[bool], outputs: &mut Vec<(WireId, bool)>) {
+        let value = |wire: &WireId| wires[wire.index()];
+        match self {
+            Gate::XOR { input_a, input_b, output } => outputs.push((*output, value(input_a) ^ value(input_b))),
+            Gate::AND { input_a, input_b, output } => outputs.push((*output, value(input_a) & value(input_b))),
+            Gate::INV { input, output } => outputs.push((*output, !value(input))),
+            Gate::EQ { constant, output } => outputs.push((*output, *constant)),
+            Gate::EQW { input, output } => outputs.push((*output, value(input))),
+            Gate::MAND { inputs, outputs: wires } => {
+                let (inputs_a, inputs_b) = inputs.split_at(wires.len());
+                outputs.extend(inputs_a.iter().zip(inputs_b).zip(wires).map(|((a, b), output)| (*output, value(a) & value(b))));
+            }
+        }
+    }
+}
+
+/// Formats the gate as a line of a bristol file, e.g. `2 1 42 43 44 XOR`
+impl Display for Gate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Gate::XOR { input_a, input_b, output } => write!(f, "2 1 {input_a} {input_b} {output} XOR"),
+            Gate::AND { input_a, input_b, output } => write!(f, "2 1 {input_a} {input_b} {output} AND"),
+            Gate::INV { input, output } => write!(f, "1 1 {input} {output} INV"),
+            Gate::EQ { constant, output } => write!(f, "1 1 {} {output} EQ", *constant as u8),
+            Gate::EQW { input, output } => write!(f, "1 1 {input} {output} EQW"),
+            Gate::MAND { inputs, outputs } =>
+                write!(f, "{} {} {} {} MAND", inputs.len(), outputs.len(), join_wires(inputs), join_wires(outputs)),
+        }
+    }
+}
+
+/// Joins a list of wires (or port widths) into a space separated String
+fn join_wires<T: Display>(wires: &[T]) -> String {
+    wires.iter().map(T::to_string).collect::<Vec<_>>().join(" ")
+}
+
+
+/// The flavour of the bristol format a circuit is written in
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum CircuitFormat {
+    // The original bristol format, whose header has two lines: the number of gates and wires, and the
+    // number of wires of the two input ports and the single output port (e.g. `128 128 128`).
+    // Only XOR, AND, and INV gates are allowed. This is the default format, files in the fashion
+    // format (like the ones written by `write`) need to be parsed with the `_with_format` functions.
+    #[default]
+    Classic,
+    // The bristol fashion format, whose header has three lines: the number of gates and wires, followed
+    // by the number of input and output ports, each with the number of wires per port (e.g. `2 128 128`).
+    // Additionally allows EQ, EQW, and MAND gates.
+    Fashion,
+}
+
+impl CircuitFormat {
+    fn num_header_lines(self) -> usize {
+        match self {
+            CircuitFormat::Classic => 2,
+            CircuitFormat::Fashion => 3,
+        }
+    }
+
+    /// Ensures that the gate may be used in a circuit of this format
+    fn check_gate(self, gate: &Gate, line: u32) -> Result<(), ParseError> {
+        match (self, gate) {
+            (CircuitFormat::Classic, Gate::EQ { .. } | Gate::EQW { .. } | Gate::MAND { .. }) => Err(InvalidEntry {
+                line,
+                description: format!("{} gates are only allowed in the bristol fashion format", gate.type_name()),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Header {
     // Header information of a bristol circuit
 
@@ -53,203 +548,4287 @@ pub struct Header {
     // Number of input/output ports may be inferred from the length of the Vecs
     num_input_wires: Vec<u32>,
     num_output_wires: Vec<u32>,
-}
 
-#[derive(Debug, PartialEq)]
-pub struct Circuit {
-    // a circuit consists of a header and the gates of a circuit
-    header: Header,
-    gates: Vec<Gate>,
+    // Type of each input/output port, which plain bristol files do not declare.
+    // If the types are missing, every port holds plain bits.
+    input_types: Option<Vec<PortType>>,
+    output_types: Option<Vec<PortType>>,
 }
 
-impl Circuit {
-    /// Parses the bristol file contents into a circuit
-    pub fn parse(circuit: &str) -> Self {
-        // Collect all non-empty lines of the str input into a Vec
-        let circuit: Vec<&str> = circuit.lines().filter(|line| !line.is_empty()).collect();
+impl Header {
+    /// Returns the total number of gates declared by the header
+    pub fn num_gates(&self) -> u32 {
+        self.num_gates
+    }
 
-        let header = parse_header(&circuit[0..3]);
+    /// Returns the total number of wires declared by the header
+    pub fn num_wires(&self) -> u32 {
+        self.num_wires
+    }
 
-        let mut gates: Vec<Gate> = Vec::new();
-        for line in &circuit[3..] {
-            gates.push(parse_gate(line));
-        }
+    /// Returns the number of wires of each input port
+    pub fn input_wires(&self) -> &[u32] {
+        &self.num_input_wires
+    }
 
-        Circuit { header, gates }
+    /// Returns the number of wires of each output port
+    pub fn output_wires(&self) -> &[u32] {
+        &self.num_output_wires
     }
-}
 
-/// Parses the bristol file header, expecting to get the first three lines as an argument
-fn parse_header(header_lines: &[&str]) -> Header {
-    let (num_gates, num_wires) = parse_header_general(header_lines[0]);
-    let num_input_wires = parse_header_io_wires(header_lines[1]);
-    let num_output_wires = parse_header_io_wires(header_lines[2]);
+    /// Returns the type of the given input port, or None if there is no such port
+    pub fn input_type(&self, port: usize) -> Option<PortType> {
+        port_type(self.input_types.as_deref(), self.num_input_wires.len(), port)
+    }
 
-    Header {
-        num_gates,
-        num_wires,
-        num_input_wires,
-        num_output_wires,
+    /// Returns the type of the given output port, or None if there is no such port
+    pub fn output_type(&self, port: usize) -> Option<PortType> {
+        port_type(self.output_types.as_deref(), self.num_output_wires.len(), port)
     }
-}
 
-/// Parses the first line of the bristol file header
-/// returns: (the total number of gates, the total number of wires)
-fn parse_header_general(header_line: &str) -> (u32, u32) {
-    let header_line: Vec<&str> = header_line.split_whitespace().collect();
-    (header_line[0].parse().unwrap(),
-     header_line[1].parse().unwrap())
-}
+    /// Returns the types of all input ports
+    fn all_input_types(&self) -> Vec<PortType> {
+        (0..self.num_input_wires.len()).filter_map(|port| self.input_type(port)).collect()
+    }
 
-/// Parses the second/third line of the bristol file header containing the number of wires per input/output
-fn parse_header_io_wires(header_line: &str) -> Vec<u32> {
-    let header_line: Vec<&str> = header_line.split_whitespace().collect();
-    let num_ports: usize = header_line[0].parse().unwrap();
-    let mut num_wires: Vec<u32> = Vec::new();
+    /// Returns the types of all output ports
+    fn all_output_types(&self) -> Vec<PortType> {
+        (0..self.num_output_wires.len()).filter_map(|port| self.output_type(port)).collect()
+    }
+}
 
-    assert_eq!(header_line[1..].len(), num_ports);
-    for line_part in &header_line[1..] {
-        num_wires.push(line_part.parse().unwrap());
+/// Returns the type of one of the given number of ports, which hold plain bits if they have no types
+fn port_type(types: Option<&[PortType]>, num_ports: usize, port: usize) -> Option<PortType> {
+    match types {
+        Some(types) => types.get(port).copied(),
+        None => (port < num_ports).then_some(PortType::Bits),
     }
+}
 
-    num_wires
+/// Options controlling how bristol files are parsed
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ParseOptions {
+    pub format: CircuitFormat,
+    // Strict parsing rejects annotations after the gate type, and validates the parsed circuit (see
+    // `Circuit::validate`). Otherwise, the parser accepts any circuit it is able to read.
+    pub strict: bool,
+    // Limits on the number of gates and wires, which abort parsing as soon as the header declares or
+    // the gates reference more of them, e.g. to parse circuits from untrusted sources
+    pub max_gates: Option<u32>,
+    pub max_wires: Option<u32>,
 }
 
-/// Parses a line of the bristol file describing one gate
-fn parse_gate(gate_line: &str) -> Gate {
-    let gate_line: Vec<&str> = gate_line.split_whitespace().collect();
+impl ParseOptions {
+    /// Checks the declared numbers of gates and wires against the limits
+    fn check_header_limits(&self, header: &Header, line: u32) -> Result<(), ParseError> {
+        check_limit("gates", self.max_gates, header.num_gates as u64, line)?;
+        check_limit("wires", self.max_wires, header.num_wires as u64, line)
+    }
 
-    match *gate_line.last().unwrap() {
-        "XOR" => parse_gate_xor(&gate_line),
-        "AND" => parse_gate_and(&gate_line),
-        "INV" | "NOT" => parse_gate_inv(&gate_line),
-        "EQ" | "EQW" | "MAND" => unimplemented!(),
-        _ => panic!("Unknown gate type!")
+    /// Checks the number of gates parsed so far and the wires of the latest gate against the limits
+    fn check_gate_limits(&self, num_gates: u64, gate: &Gate, line: u32) -> Result<(), ParseError> {
+        check_limit("gates", self.max_gates, num_gates, line)?;
+        // this runs for every gate, so the wires are only looked at if they are limited
+        match self.max_wires.and_then(|_| gate.max_wire()) {
+            Some(wire) => check_limit("wires", self.max_wires, wire.0 as u64 + 1, line),
+            None => Ok(()),
+        }
     }
 }
 
-/// helper function to parse a XOR gate line
-fn parse_gate_xor(gate_line: &[&str]) -> Gate {
-    // ensure that the number of input and output wires in the gate_line_vec is correct
-    assert_eq!(gate_line[0], "2", "Number of input wires must be 2 for every XOR gate");
-    assert_eq!(gate_line[1], "1", "Number of output wires must be 1 for every gate");
+/// Fails if the found number of gates or wires exceeds the limit, if there is one
+fn check_limit(resource: &'static str, limit: Option<u32>, found: u64, line: u32) -> Result<(), ParseError> {
+    match limit {
+        Some(limit) if found > limit as u64 => Err(LimitExceeded { line, resource, limit, found }),
+        _ => Ok(()),
+    }
+}
 
-    let input_a: u32 = gate_line[2].parse().unwrap();
-    let input_b: u32 = gate_line[3].parse().unwrap();
-    let output: u32 = gate_line[4].parse().unwrap();
+/// How the wires of a port are interpreted, with the least significant bit on the lowest wire
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PortType {
+    // a plain sequence of bits without any numeric interpretation
+    #[default]
+    Bits,
+    UnsignedInt,
+    // an integer in two's complement, with the sign bit on the highest wire of the port
+    SignedInt,
+}
 
-    Gate::XOR { input_a, input_b, output }
+/// Where a wire of a circuit belongs to, see `Circuit::port_of_wire`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PortKind {
+    Input,
+    Output,
+    // a wire between the input and the output ports, which is assigned by a gate
+    Internal,
 }
 
-/// helper function to parse a AND gate line
-fn parse_gate_and(gate_line: &[&str]) -> Gate {
-    // ensure that the number of input and output wires in the gate_line_vec is correct
-    assert_eq!(gate_line[0], "2", "Number of input wires must be 2 for every AND gate");
-    assert_eq!(gate_line[1], "1", "Number of output wires must be 1 for every gate");
+/// An operation over the field with two elements, keyed by the wires of the circuit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArithOp {
+    // addition mod 2, corresponding to a XOR
+    Add { input_a: WireId, input_b: WireId, output: WireId },
+    // multiplication mod 2, corresponding to an AND
+    Mul { input_a: WireId, input_b: WireId, output: WireId },
+    // `1 - x`, corresponding to an INV
+    Not { input: WireId, output: WireId },
+    Const { value: bool, output: WireId },
+    Copy { input: WireId, output: WireId },
+}
 
-    let input_a: u32 = gate_line[2].parse().unwrap();
-    let input_b: u32 = gate_line[3].parse().unwrap();
-    let output: u32 = gate_line[4].parse().unwrap();
+/// Number of gates of each type in a circuit, ignoring EQ and EQW gates as they are free
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct GateCounts {
+    pub xor: usize,
+    pub and: usize,
+    pub inv: usize,
+    pub mand: usize,
+}
 
-    Gate::AND { input_a, input_b, output }
+/// Weights of the gate types for estimating the cost of a circuit with `estimate_cost`.
+/// MAND gates are charged as their individual ANDs, i.e. `and` once per output.
+/// The default is the free-XOR model, where each AND costs 1 and all other gates are free.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CostModel {
+    pub xor: u64,
+    pub and: u64,
+    pub inv: u64,
+    pub eq: u64,
+    pub eqw: u64,
 }
 
-/// helper function to parse a NOT/INV gate line
-fn parse_gate_inv(gate_line: &[&str]) -> Gate {
-    // ensure that the number of input and output wires in the gate_line_vec is correct
-    assert_eq!(gate_line[0], "1", "Number of input wires must be 1 for every INV/NOT gate");
-    assert_eq!(gate_line[1], "1", "Number of output wires must be 1 for every gate");
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel { xor: 0, and: 1, inv: 0, eq: 0, eqw: 0 }
+    }
+}
 
-    let input: u32 = gate_line[2].parse().unwrap();
-    let output: u32 = gate_line[3].parse().unwrap();
+/// Differences between two circuits as computed by `Circuit::diff`. Gates are compared as a
+/// multiset, so the same gates in a different order are no difference.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct CircuitDiff {
+    // gates only present in the first circuit, in its order
+    pub removed: Vec<Gate>,
+    // gates only present in the second circuit, in its order
+    pub added: Vec<Gate>,
+    // the headers of both circuits, if they differ
+    pub header: Option<(Header, Header)>,
+}
 
-    Gate::INV { input, output }
+impl CircuitDiff {
+    /// Returns whether both circuits have the same header and the same gates
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty() && self.header.is_none()
+    }
 }
-/// try to get the next element from an iterator or return a SyntaxError if there is no next element
-fn get_next_or_error<I, T>(iter: &mut I, line: u32, error_msg: &str) -> Result<T, ParserError>
-    where I: Iterator<Item=T> {
-    iter.next().ok_or_else(|| SyntaxError { line, description: error_msg.to_string() })
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Circuit {
+    // a circuit consists of a header and the gates of a circuit
+    header: Header,
+    gates: Vec<Gate>,
 }
-fn parse_non_negative_number(text: &str, line: u32) -> Result<u32, ParserError> {
-    match text.parse() {
-        Ok(parsed) => Ok(parsed),
-        _ => Err(UnexpectedToken { line, expected: TokenType::NonNegativeNumber, got: text.to_string() })
+
+impl Circuit {
+    /// Returns the header of the circuit
+    pub fn header(&self) -> &Header {
+        &self.header
     }
-}
 
-// A `#[cfg(test)]` marks the following block as conditionally included only for test builds.
-// cfg directives can achieve similar things as preprocessor directives in C/C++.
-#[cfg(test)]
-mod tests {
-    use std::fs::read_to_string;
-    use crate::circuit::*;
+    /// Returns the gates of the circuit in their stored order
+    pub fn gates(&self) -> &[Gate] {
+        &self.gates
+    }
 
-    #[test]
-    fn test_parse_header() {
-        let input = vec!["42 1337", "3 10 20 30", "2 10 20"];
-        let output = parse_header(&input);
+    /// Parses the bristol file contents in the (default) classic format into a circuit
+    pub fn parse(circuit: &str) -> Result<Self, ParseError> {
+        Self::parse_with_format(circuit, CircuitFormat::default())
+    }
 
-        assert_eq!(output.num_gates, 42);
-        assert_eq!(output.num_wires, 1337);
-        assert_eq!(output.num_input_wires, vec![10, 20, 30]);
-        assert_eq!(output.num_output_wires, vec![10, 20]);
+    /// Parses the bristol file contents in the given format into a circuit
+    pub fn parse_with_format(circuit: &str, format: CircuitFormat) -> Result<Self, ParseError> {
+        Self::parse_with_options(circuit, ParseOptions { format, ..ParseOptions::default() })
     }
 
-    #[test]
-    fn test_parse_gate() {
-        let input_xor = "2 1 42 43 44 XOR";
-        let input_inv = "1 1 16 17 INV";
-        let output_xor = parse_gate(input_xor);
-        let output_inv = parse_gate(input_inv);
+    /// Parses the bristol file contents into a circuit as specified by the options
+    pub fn parse_with_options(circuit: &str, options: ParseOptions) -> Result<Self, ParseError> {
+        Self::parse_lines(circuit.lines().map(Ok), options)
+    }
 
-        assert_eq!(output_xor, Gate::XOR { input_a: 42, input_b: 43, output: 44 });
-        assert_eq!(output_inv, Gate::INV { input: 16, output: 17 });
+    /// Parses the bristol file contents like `parse_with_options`, and additionally returns the
+    /// annotations after the gate types, keyed by the index of the annotated gate. Strict parsing
+    /// rejects annotations, so there are none in that case.
+    pub fn parse_annotated(circuit: &str, options: ParseOptions) -> Result<(Self, BTreeMap<usize, String>), ParseError> {
+        let mut sink = Annotated::default();
+        let header = Self::visit_lines(circuit.lines().map(Ok), options, &mut sink)?;
+        let circuit = Circuit { header, gates: sink.gates };
+        if options.strict {
+            circuit.validate()?;
+        }
+        Ok((circuit, sink.annotations))
     }
 
-    #[test]
-    fn test_parse_tiny_circuit() {
-        let input = "4 8\n\
-            4 1 1 1 1\n\
-            1 1\n\
-            \n\
-            2 1 0 1 4 AND\n\
-            2 1 2 3 5 AND\n\
-            2 1 4 5 6 AND\n\
-            1 1 6 7 INV";
-        let output = Circuit::parse(input);
+    /// Parses the bristol file contents from raw bytes. As bristol files are plain ASCII, the bytes
+    /// are only checked to be ASCII instead of validating them as UTF-8, which is faster for large files.
+    pub fn parse_bytes(circuit: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_bytes_with_format(circuit, CircuitFormat::default())
+    }
 
-        assert_eq!(output.header, Header { num_gates: 4, num_wires: 8, num_input_wires: vec![1, 1, 1, 1], num_output_wires: vec![1] });
-        assert_eq!(output.gates[0], Gate::AND { input_a: 0, input_b: 1, output: 4 });
-        assert_eq!(output.gates[1], Gate::AND { input_a: 2, input_b: 3, output: 5 });
-        assert_eq!(output.gates[2], Gate::AND { input_a: 4, input_b: 5, output: 6 });
-        assert_eq!(output.gates[3], Gate::INV { input: 6, output: 7 });
+    /// Parses the bristol file contents in the given format from raw bytes, like `parse_bytes`
+    pub fn parse_bytes_with_format(circuit: &[u8], format: CircuitFormat) -> Result<Self, ParseError> {
+        if let Some(position) = circuit.iter().position(|byte| !byte.is_ascii()) {
+            let line_start = circuit[..position].iter().rposition(|&byte| byte == b'\n').map_or(0, |newline| newline + 1);
+            let line = circuit[..position].iter().filter(|&&byte| byte == b'\n').count() as u32 + 1;
+            return Err(NonAscii { line, column: (position - line_start) as u32 + 1 });
+        }
+        // SAFETY: the bytes are all ASCII, which is always valid UTF-8
+        let circuit = unsafe { core::str::from_utf8_unchecked(circuit) };
+        Self::parse_with_format(circuit, format)
     }
 
-    #[test]
-    fn test_parse_big_circuit() {
-        let input = read_to_string("res/aes_128.txt").unwrap();
-        let output = Circuit::parse(&input);
+    #[cfg(feature = "std")]
+    /// Parses a bristol file line by line from the reader, without reading the whole file into memory first
+    pub fn parse_reader<R: BufRead>(reader: R) -> Result<Self, ParseError> {
+        Self::parse_reader_with_format(reader, CircuitFormat::default())
+    }
 
-        // Simple plausibility check: Assert that all gates have been parsed
-        assert_eq!(
-            output.gates.iter().filter(
-                |g| matches!(g, Gate::AND {input_a:_, input_b:_, output:_} )
-            ).count(),
-            6400
+    #[cfg(feature = "std")]
+    /// Parses a bristol file in the given format line by line from the reader
+    pub fn parse_reader_with_format<R: BufRead>(reader: R, format: CircuitFormat) -> Result<Self, ParseError> {
+        Self::parse_lines(reader.lines().map(|line| Ok(line?)), ParseOptions { format, ..ParseOptions::default() })
+    }
+
+    #[cfg(feature = "std")]
+    /// Parses a bristol file line by line from the reader and passes the header and then each gate to
+    /// the visitor as soon as it is parsed, without storing the gates
+    pub fn parse_streaming<R: BufRead, V: GateVisitor>(reader: R, visitor: &mut V) -> Result<(), ParseError> {
+        Self::parse_streaming_with_format(reader, CircuitFormat::default(), visitor)
+    }
+
+    #[cfg(feature = "std")]
+    /// Parses a bristol file in the given format line by line from the reader and passes the header
+    /// and then each gate to the visitor, like `parse_streaming`
+    pub fn parse_streaming_with_format<R: BufRead, V: GateVisitor>(reader: R, format: CircuitFormat, visitor: &mut V) -> Result<(), ParseError> {
+        let options = ParseOptions { format, ..ParseOptions::default() };
+        Self::visit_lines(reader.lines().map(|line| Ok(line?)), options, &mut Visiting(visitor)).map(|_| ())
+    }
+
+    /// Parses the lines of a bristol file, the first non-empty lines being the header
+    fn parse_lines<L: AsRef<str>>(lines: impl Iterator<Item=Result<L, ParseError>>, options: ParseOptions) -> Result<Self, ParseError> {
+        let mut gates: Vec<Gate> = Vec::new();
+        let header = Self::visit_lines(lines, options, &mut gates)?;
+        let circuit = Circuit { header, gates };
+        if options.strict {
+            circuit.validate()?;
+        }
+        Ok(circuit)
+    }
+
+    /// Parses the lines of a bristol file, passing the gates to the sink, and returns the header
+    fn visit_lines<L: AsRef<str>>(lines: impl Iterator<Item=Result<L, ParseError>>, options: ParseOptions, sink: &mut impl GateSink) -> Result<Header, ParseError> {
+        let format = options.format;
+        let num_header_lines = format.num_header_lines();
+        let mut header_lines: Vec<(u32, L)> = Vec::with_capacity(num_header_lines);
+        let mut header: Option<Header> = None;
+        let mut num_gates: u64 = 0;
+
+        // Line numbers are 1-based and count all lines, including empty lines and comments
+        for (index, line) in lines.enumerate() {
+            let line = line?;
+            let line_number = index as u32 + 1;
+            let Some(content) = content_of_line(line.as_ref()) else {
+                continue;
+            };
+
+            match header {
+                Some(_) => {
+                    let (gate, annotation) = parse_gate(content, line_number)?;
+                    format.check_gate(&gate, line_number)?;
+                    num_gates += 1;
+                    options.check_gate_limits(num_gates, &gate, line_number)?;
+                    match annotation {
+                        Some((column, annotation)) if options.strict => return Err(SyntaxError {
+                            line: line_number,
+                            description: format!("Unexpected annotation {annotation:?} after the gate type at column {column}"),
+                        }),
+                        _ => {}
+                    }
+                    sink.gate(gate);
+                    if let Some((_, annotation)) = annotation {
+                        sink.annotation(annotation);
+                    }
+                }
+                None => {
+                    check_header_line(content, line_number)?;
+                    header_lines.push((line_number, line));
+                    if header_lines.len() == num_header_lines {
+                        let header_lines: Vec<(u32, &str)> = header_lines.iter()
+                            .filter_map(|(line_number, line)| Some((*line_number, content_of_line(line.as_ref())?)))
+                            .collect();
+                        let parsed = match format {
+                            CircuitFormat::Classic => parse_header_classic(&header_lines)?,
+                            CircuitFormat::Fashion => parse_header(&header_lines)?,
+                        };
+                        options.check_header_limits(&parsed, line_number)?;
+                        sink.header(&parsed);
+                        header = Some(parsed);
+                    }
+                }
+            }
+        }
+
+        header.ok_or(TooFewLines { expected: num_header_lines, got: header_lines.len() })
+    }
+
+    #[cfg(feature = "std")]
+    /// Reads the bristol file at the given path and parses it into a circuit
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ParseError> {
+        Self::from_file_with_format(path, CircuitFormat::default())
+    }
+
+    #[cfg(feature = "std")]
+    /// Reads the bristol file in the given format at the given path and parses it into a circuit
+    pub fn from_file_with_format<P: AsRef<Path>>(path: P, format: CircuitFormat) -> Result<Self, ParseError> {
+        Self::parse_with_format(&read_to_string(path)?, format)
+    }
+
+    /// Returns the number of AND gates, which determines the online cost of the circuit in MPC
+    pub fn and_count(&self) -> usize {
+        self.gates.iter().filter(|gate| matches!(gate, Gate::AND { .. })).count()
+    }
+
+    /// Returns the number of XOR, AND, INV, and MAND gates in the circuit
+    pub fn gate_counts(&self) -> GateCounts {
+        let mut counts = GateCounts::default();
+        for gate in &self.gates {
+            match gate {
+                Gate::XOR { .. } => counts.xor += 1,
+                Gate::AND { .. } => counts.and += 1,
+                Gate::INV { .. } => counts.inv += 1,
+                Gate::MAND { .. } => counts.mand += 1,
+                Gate::EQ { .. } | Gate::EQW { .. } => {}
+            }
+        }
+        counts
+    }
+
+    /// Returns the bristol identifiers of all gate types used in the circuit, e.g. `{"AND", "INV"}`
+    pub fn gate_types_used(&self) -> BTreeSet<&'static str> {
+        self.gates.iter().map(Gate::type_name).collect()
+    }
+
+    /// Returns whether the circuit is linear, i.e. contains no AND or MAND gates, which makes it free
+    /// to evaluate in most MPC protocols
+    pub fn is_linear(&self) -> bool {
+        !self.gates.iter().any(Gate::is_nonlinear)
+    }
+
+    /// Partitions the indices of the gates into the free linear gates (XOR, INV, EQ, and EQW) and the
+    /// costly nonlinear gates (AND and MAND), both in the stored order of the gates
+    pub fn split_linear_nonlinear(&self) -> (Vec<usize>, Vec<usize>) {
+        (0..self.gates.len()).partition(|&index| !self.gates[index].is_nonlinear())
+    }
+
+    /// Returns the number of multiplication triples needed to evaluate the circuit in the GMW protocol,
+    /// which is one per AND gate and one per output of a MAND gate
+    pub fn triples_required(&self) -> usize {
+        self.gates.iter()
+            .map(|gate| match gate {
+                Gate::AND { .. } => 1,
+                Gate::MAND { outputs, .. } => outputs.len(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Returns the summed weights of all gates in the circuit under the given cost model
+    pub fn estimate_cost(&self, model: &CostModel) -> u64 {
+        self.gates.iter()
+            .map(|gate| match gate {
+                Gate::XOR { .. } => model.xor,
+                Gate::AND { .. } => model.and,
+                Gate::INV { .. } => model.inv,
+                Gate::EQ { .. } => model.eq,
+                Gate::EQW { .. } => model.eqw,
+                Gate::MAND { outputs, .. } => model.and * outputs.len() as u64,
+            })
+            .sum()
+    }
+
+    /// Returns whether any wire of the output port is reachable from a wire of the input port in the
+    /// dependency graph of the gates. If not, the output structurally ignores the input, while the
+    /// converse does not hold, as e.g. `a ^ a` depends on `a` structurally but not semantically.
+    /// Panics if the circuit contains a cycle.
+    pub fn output_depends_on(&self, output_idx: usize, input_idx: usize) -> bool {
+        let mut reached = vec![false; self.header.num_wires as usize];
+        for wire in &self.input_wire_indices()[input_idx] {
+            reached[wire.index()] = true;
+        }
+        for index in self.topological_order().expect("dependencies are only defined for acyclic circuits") {
+            let gate = &self.gates[index];
+            if gate.inputs().iter().any(|wire| reached[wire.index()]) {
+                for wire in gate.outputs() {
+                    reached[wire.index()] = true;
+                }
+            }
+        }
+        self.output_wire_indices()[output_idx].iter().any(|wire| reached[wire.index()])
+    }
+
+    /// Compares the circuit to another one, e.g. the result of an optimization pass. Identical gates
+    /// are matched regardless of their position, so only gates that were actually removed, added,
+    /// or changed (which shows up as one removed and one added gate) are reported.
+    pub fn diff(&self, other: &Circuit) -> CircuitDiff {
+        // Returns the gates of the first circuit that have no identical counterpart in the second one
+        let unmatched = |gates: &[Gate], others: &[Gate]| -> Vec<Gate> {
+            let mut counts: BTreeMap<&Gate, usize> = BTreeMap::new();
+            for gate in others {
+                *counts.entry(gate).or_default() += 1;
+            }
+            gates.iter()
+                .filter(|gate| match counts.get_mut(gate) {
+                    Some(count) if *count > 0 => {
+                        *count -= 1;
+                        false
+                    }
+                    _ => true,
+                })
+                .cloned()
+                .collect()
+        };
+        let (removed, added) = (unmatched(&self.gates, &other.gates), unmatched(&other.gates, &self.gates));
+
+        let header = (self.header != other.header).then(|| (self.header.clone(), other.header.clone()));
+        CircuitDiff { removed, added, header }
+    }
+
+    /// Returns the multiplicative depth of the circuit, i.e. the maximum number of AND (or MAND) gates
+    /// on any path from the inputs to an output wire.
+    /// Panics if the circuit contains a cycle.
+    pub fn and_depth(&self) -> usize {
+        let depths = self.wire_depths(|gate| matches!(gate, Gate::AND { .. } | Gate::MAND { .. }) as usize);
+        self.output_wire_range().map(|wire| depths.get(&WireId(wire)).copied().unwrap_or(0)).max().unwrap_or(0)
+    }
+
+    /// Returns the total depth of the circuit, i.e. the maximum number of gates of any type on any
+    /// path from the inputs to an output wire.
+    /// Panics if the circuit contains a cycle.
+    pub fn depth(&self) -> usize {
+        let depths = self.wire_depths(|_| 1);
+        self.output_wire_range().map(|wire| depths.get(&WireId(wire)).copied().unwrap_or(0)).max().unwrap_or(0)
+    }
+
+    /// Returns a fingerprint of the structure of the circuit, which does not depend on the order the
+    /// gates are stored in or the numbering of the internal wires. Each wire is hashed from the gate
+    /// producing it and the hashes of its inputs, and the fingerprint combines the hashes of the
+    /// output wires. Gates that do not contribute to any output do not change the fingerprint.
+    /// The fingerprint is only stable between builds using the same version of the std library.
+    /// Panics if the circuit contains a cycle.
+    pub fn structural_fingerprint(&self) -> u64 {
+        let order = self.topological_order().expect("fingerprints are only defined for acyclic circuits");
+        // Wires without a producing gate are identified by their wire index, i.e. their input port
+        let mut wire_hashes: BTreeMap<WireId, u64> = BTreeMap::new();
+        let wire_hash = |wire_hashes: &BTreeMap<WireId, u64>, wire: WireId| {
+            wire_hashes.get(&wire).copied().unwrap_or_else(|| hash_of(("input", wire)))
+        };
+        for gate in order.into_iter().map(|index| &self.gates[index]) {
+            match gate {
+                Gate::XOR { input_a, input_b, output } | Gate::AND { input_a, input_b, output } => {
+                    let (a, b) = (wire_hash(&wire_hashes, *input_a), wire_hash(&wire_hashes, *input_b));
+                    wire_hashes.insert(*output, hash_of((gate.type_name(), a, b)));
+                }
+                Gate::INV { input, output } => {
+                    let input = wire_hash(&wire_hashes, *input);
+                    wire_hashes.insert(*output, hash_of(("INV", input)));
+                }
+                Gate::EQ { constant, output } => {
+                    wire_hashes.insert(*output, hash_of(("EQ", constant)));
+                }
+                // copies are transparent, so they do not change the hash of the wire
+                Gate::EQW { input, output } => {
+                    wire_hashes.insert(*output, wire_hash(&wire_hashes, *input));
+                }
+                // each output of a MAND is hashed like the output of a separate AND
+                Gate::MAND { inputs, outputs } => {
+                    let (inputs_a, inputs_b) = inputs.split_at(outputs.len());
+                    for ((input_a, input_b), output) in inputs_a.iter().zip(inputs_b).zip(outputs) {
+                        let (a, b) = (wire_hash(&wire_hashes, *input_a), wire_hash(&wire_hashes, *input_b));
+                        wire_hashes.insert(*output, hash_of(("AND", a, b)));
+                    }
+                }
+            }
+        }
+
+        let outputs: Vec<u64> = self.output_wire_range().map(|wire| wire_hash(&wire_hashes, WireId(wire))).collect();
+        hash_of((&self.header.num_input_wires, &self.header.num_output_wires, &outputs))
+    }
+
+    /// Computes for each produced wire the maximum sum of gate weights along any path from the inputs to it
+    fn wire_depths(&self, weight: impl Fn(&Gate) -> usize) -> BTreeMap<WireId, usize> {
+        let order = self.topological_order().expect("depth is only defined for acyclic circuits");
+
+        // Circuit inputs have depth 0 and are therefore not stored
+        let mut depths: BTreeMap<WireId, usize> = BTreeMap::new();
+        for gate in order.into_iter().map(|index| &self.gates[index]) {
+            let input_depth = gate.inputs().iter().map(|wire| depths.get(wire).copied().unwrap_or(0)).max().unwrap_or(0);
+            for output in gate.outputs() {
+                depths.insert(output, input_depth + weight(gate));
+            }
+        }
+        depths
+    }
+
+    /// Maps each wire read by any gate to the number of gate inputs connected to it.
+    /// If `count_outputs` is set, the output wires of the circuit count as one additional consumer.
+    pub fn fan_out(&self, count_outputs: bool) -> BTreeMap<WireId, usize> {
+        let mut fan_out: BTreeMap<WireId, usize> = BTreeMap::new();
+        for wire in self.gates.iter().flat_map(Gate::inputs) {
+            *fan_out.entry(wire).or_default() += 1;
+        }
+        if count_outputs {
+            for wire in self.output_wire_range() {
+                *fan_out.entry(WireId(wire)).or_default() += 1;
+            }
+        }
+        fan_out
+    }
+
+    /// Returns for each wire read by a gate the index of the last gate reading it, after which the
+    /// value of the wire is no longer needed when evaluating the gates in their stored order.
+    /// Output wires of the circuit are needed until the end and are therefore not included.
+    /// The entries are sorted by wire.
+    pub fn wire_liveness(&self) -> Vec<(WireId, usize)> {
+        let mut last_use: BTreeMap<WireId, usize> = BTreeMap::new();
+        for (index, gate) in self.gates.iter().enumerate() {
+            for wire in gate.inputs() {
+                last_use.insert(wire, index);
+            }
+        }
+
+        let outputs = self.output_wire_range();
+        let mut liveness: Vec<(WireId, usize)> = last_use.into_iter().filter(|(wire, _)| !outputs.contains(&wire.0)).collect();
+        liveness.sort_unstable();
+        liveness
+    }
+
+    /// Returns the input port wires and gate output wires which are neither read by any gate
+    /// nor an output of the circuit, in ascending order
+    pub fn unused_wires(&self) -> Vec<WireId> {
+        let used: BTreeSet<WireId> = self.gates.iter().flat_map(Gate::inputs).collect();
+        let outputs = self.output_wire_range();
+
+        let mut unused: Vec<WireId> = self.input_wire_range().map(WireId)
+            .chain(self.gates.iter().flat_map(Gate::outputs))
+            .filter(|wire| !used.contains(wire) && !outputs.contains(&wire.0))
+            .collect();
+        unused.sort_unstable();
+        unused.dedup();
+        unused
+    }
+
+    /// Returns the range of wires belonging to the input ports, which are the lowest wires of the circuit
+    fn input_wire_range(&self) -> Range<u32> {
+        0..self.header.num_input_wires.iter().sum()
+    }
+
+    /// Returns the number of wires the circuit needs, which is the declared number of wires unless a
+    /// gate references a wire beyond it
+    fn wire_bound(&self) -> u64 {
+        let max_wire = self.gates.iter().filter_map(Gate::max_wire).max();
+        max_wire.map_or(0, |wire| wire.0 as u64 + 1).max(self.header.num_wires as u64)
+    }
+
+    /// Returns the number of wires the circuit actually references, i.e. one more than the highest
+    /// wire belonging to an input port or read or assigned by any gate, independent of the header
+    pub fn computed_wire_count(&self) -> u32 {
+        let num_input_wires = self.input_wire_range().end;
+        self.gates.iter()
+            .filter_map(Gate::max_wire)
+            .map(|wire| wire.0.saturating_add(1))
+            .fold(num_input_wires, u32::max)
+    }
+
+    /// Returns the range of wires belonging to the output ports, which are the highest wires of the circuit
+    fn output_wire_range(&self) -> Range<u32> {
+        let num_output_wires: u32 = self.header.num_output_wires.iter().sum();
+        self.header.num_wires - num_output_wires..self.header.num_wires
+    }
+
+    /// Checks that the circuit is consistent with its header, i.e. that the numbers of gates and wires
+    /// match and that all referenced wires exist, and that every wire is assigned at most once, either
+    /// by an input port or by a single gate, while every wire read by a gate is assigned
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.gates.len() != self.header.num_gates as usize {
+            return Err(ValidationError::GateCountMismatch { declared: self.header.num_gates, actual: self.gates.len() });
+        }
+
+        for (index, gate) in self.gates.iter().enumerate() {
+            let out_of_range = gate.inputs().into_iter()
+                .chain(gate.outputs())
+                .find(|wire| wire.0 >= self.header.num_wires);
+            if let Some(wire) = out_of_range {
+                return Err(ValidationError::WireOutOfRange { gate: index, wire, num_wires: self.header.num_wires });
+            }
+        }
+
+        // wires beyond the header are reported above, so this only catches headers declaring unused wires
+        let computed = self.computed_wire_count();
+        if computed != self.header.num_wires {
+            return Err(ValidationError::WireCountMismatch { declared: self.header.num_wires, computed });
+        }
+
+        let input_wires = self.input_wire_range();
+        let mut assigned_by: BTreeMap<WireId, usize> = BTreeMap::new();
+        for (index, gate) in self.gates.iter().enumerate() {
+            for wire in gate.outputs() {
+                if input_wires.contains(&wire.0) {
+                    return Err(ValidationError::InputWireAssigned { gate: index, wire });
+                }
+                if let Some(&first_gate) = assigned_by.get(&wire) {
+                    return Err(ValidationError::DuplicateOutput { wire, first_gate, second_gate: index });
+                }
+                assigned_by.insert(wire, index);
+            }
+        }
+
+        let undriven = self.gates.iter()
+            .flat_map(Gate::inputs)
+            .find(|wire| !input_wires.contains(&wire.0) && !assigned_by.contains_key(wire));
+        if let Some(wire) = undriven {
+            return Err(ValidationError::UndrivenWire(wire));
+        }
+        Ok(())
+    }
+
+    /// Shifts every wire of the circuit by the given offset and grows the number of wires accordingly.
+    /// This is meant for splicing circuits together, as the input ports of the shifted circuit no
+    /// longer occupy the lowest wires, so it can not be evaluated on its own.
+    /// Fails if the shifted wires can not be indexed by a u32.
+    pub fn renumber(&mut self, offset: u32) -> Result<(), WireOverflow> {
+        wire_count(self.wire_bound() + offset as u64)?;
+        self.gates = mem::take(&mut self.gates).into_iter()
+            .map(|gate| gate.mapped(|wire| wire.checked_add(offset).expect("the shifted wires fit into a u32")))
+            .collect();
+        self.header.num_wires += offset;
+        Ok(())
+    }
+
+    /// Compacts the wires of the circuit to a dense range, removing any wire that is neither part of
+    /// a port nor connected to a gate. The relative order of the wires is preserved, so the input
+    /// ports still occupy the lowest and the output ports the highest wires.
+    pub fn canonicalize(&mut self) {
+        let mut used: Vec<WireId> = self.input_wire_range()
+            .chain(self.output_wire_range())
+            .map(WireId)
+            .chain(self.gates.iter().flat_map(|gate| gate.inputs().into_iter().chain(gate.outputs())))
+            .collect();
+        used.sort_unstable();
+        used.dedup();
+
+        let compacted: BTreeMap<WireId, WireId> = used.iter().copied().zip((0..).map(WireId)).collect();
+        self.gates = mem::take(&mut self.gates).into_iter()
+            .map(|gate| gate.mapped(|wire| compacted[&wire]))
+            .collect();
+        self.header.num_wires = used.len() as u32;
+    }
+
+    /// Composes two circuits sequentially, s.t. the output wires of this circuit are connected to the
+    /// input wires of the next circuit. The composed circuit has the input ports of this circuit and
+    /// the output ports of the next circuit, which requires the total number of output wires of this
+    /// circuit to match the total number of input wires of the next circuit, and the composed wires to
+    /// be indexable by a u32.
+    pub fn compose(&self, next: &Circuit) -> Result<Circuit, ComposeError> {
+        let outputs = self.output_wire_range();
+        let inputs = next.input_wire_range();
+        if outputs.len() != inputs.len() {
+            return Err(ComposeError::WidthMismatch { outputs: outputs.len() as u32, inputs: inputs.len() as u32 });
+        }
+
+        // The input wires of the next circuit become the output wires of this circuit, while all
+        // other wires of the next circuit are placed above the wires of this circuit
+        wire_count(self.wire_bound() + next.wire_bound() - inputs.end as u64)?;
+        let num_wires = self.header.num_wires;
+        let connect = |WireId(wire)| WireId(if inputs.contains(&wire) { outputs.start + wire } else { num_wires + (wire - inputs.end) });
+        let gates: Vec<Gate> = self.gates.iter().cloned()
+            .chain(next.gates.iter().cloned().map(|gate| gate.mapped(connect)))
+            .collect();
+
+        let header = Header {
+            num_gates: gates.len() as u32,
+            num_wires: num_wires + (next.header.num_wires - inputs.end),
+            num_input_wires: self.header.num_input_wires.clone(),
+            num_output_wires: next.header.num_output_wires.clone(),
+            input_types: self.header.input_types.clone(),
+            output_types: next.header.output_types.clone(),
+        };
+        Ok(Circuit { header, gates })
+    }
+
+    /// Places two circuits side by side without sharing any wires. The input and output ports of the
+    /// combined circuit are the ports of this circuit followed by the ports of the other circuit.
+    /// Fails if the combined wires can not be indexed by a u32.
+    pub fn parallel(&self, other: &Circuit) -> Result<Circuit, WireOverflow> {
+        wire_count(self.wire_bound() + other.wire_bound())?;
+        let num_wires = self.header.num_wires + other.header.num_wires;
+        let num_inputs = (self.input_wire_range().len() + other.input_wire_range().len()) as u32;
+        let num_outputs = (self.output_wire_range().len() + other.output_wire_range().len()) as u32;
+
+        // The combined wires are ordered as inputs, internal wires and outputs, each of which first
+        // holds the wires of this circuit and then the wires of the other circuit
+        let place = |circuit: &Circuit, (inputs_before, internal_before, outputs_before): (u32, u32, u32), WireId(wire)| {
+            let (inputs, outputs) = (circuit.input_wire_range(), circuit.output_wire_range());
+            WireId(if inputs.contains(&wire) {
+                inputs_before + wire
+            } else if wire < outputs.start {
+                num_inputs + internal_before + (wire - inputs.end)
+            } else {
+                num_wires - num_outputs + outputs_before + (wire - outputs.start)
+            })
+        };
+        let wires_of_self = (
+            self.input_wire_range().len() as u32,
+            self.output_wire_range().start - self.input_wire_range().end,
+            self.output_wire_range().len() as u32,
         );
+        let place_a = |wire| place(self, (0, 0, 0), wire);
+        let place_b = |wire| place(other, wires_of_self, wire);
+        let gates: Vec<Gate> = self.gates.iter().cloned().map(|gate| gate.mapped(place_a))
+            .chain(other.gates.iter().cloned().map(|gate| gate.mapped(place_b)))
+            .collect();
+
+        let header = Header {
+            num_gates: gates.len() as u32,
+            num_wires,
+            num_input_wires: self.header.num_input_wires.iter().chain(&other.header.num_input_wires).copied().collect(),
+            num_output_wires: self.header.num_output_wires.iter().chain(&other.header.num_output_wires).copied().collect(),
+            input_types: (self.header.input_types.is_some() || other.header.input_types.is_some())
+                .then(|| [self.header.all_input_types(), other.header.all_input_types()].concat()),
+            output_types: (self.header.output_types.is_some() || other.header.output_types.is_some())
+                .then(|| [self.header.all_output_types(), other.header.all_output_types()].concat()),
+        };
+        Ok(Circuit { header, gates })
+    }
+
+    /// Returns a circuit computing the complement of every output bit of this circuit.
+    /// Each output wire is inverted onto a newly allocated wire, which become the output wires.
+    pub fn invert_outputs(&self) -> Circuit {
+        let mut circuit = self.clone();
+        let num_wires = self.header.num_wires;
+        for (new_wire, output) in (num_wires..).zip(self.output_wire_range()) {
+            circuit.gates.push(Gate::INV { input: WireId(output), output: WireId(new_wire) });
+        }
+        circuit.header.num_wires += self.output_wire_range().len() as u32;
+        circuit.header.num_gates = circuit.gates.len() as u32;
+        circuit
+    }
+
+    /// Specializes the circuit for known values of some input ports, given as pairs of the index of
+    /// the input port and its bits. The fixed ports are removed from the inputs of the circuit, and
+    /// the constants are folded into the remaining gates, dropping any gate that is no longer needed.
+    /// Fails if a fixed port does not exist or the number of its bits does not match its width.
+    pub fn specialize(&self, fixed: &[(usize, Vec<bool>)]) -> Result<Circuit, SpecializeError> {
+        let fixed: BTreeMap<usize, &Vec<bool>> = fixed.iter().map(|(port, bits)| (*port, bits)).collect();
+        let ports = self.input_wire_indices();
+        for (&port, bits) in &fixed {
+            let Some(wires) = ports.get(port) else {
+                return Err(SpecializeError::NoSuchPort { port, num_ports: ports.len() });
+            };
+            if bits.len() != wires.len() {
+                return Err(SpecializeError::WidthMismatch { port, width: wires.len(), bits: bits.len() });
+            }
+        }
+
+        // The wires of the remaining input ports move down to stay the lowest wires, followed by the
+        // wires of the fixed ports, which are assigned by EQ gates
+        let (remaining, fixed_wires): (Vec<_>, Vec<_>) = ports.iter().enumerate().partition(|(port, _)| !fixed.contains_key(port));
+        let mut renumbered: BTreeMap<WireId, WireId> = BTreeMap::new();
+        let mut gates = Vec::new();
+        let input_wires = remaining.iter().chain(&fixed_wires)
+            .flat_map(|(port, wires)| wires.iter().map(move |wire| (*port, *wire)));
+        for (new_wire, (port, wire)) in (0..).zip(input_wires) {
+            renumbered.insert(wire, WireId(new_wire));
+            if let Some(bits) = fixed.get(&port) {
+                gates.push(Gate::EQ { constant: bits[(wire - ports[port][0]) as usize], output: WireId(new_wire) });
+            }
+        }
+        gates.extend(self.gates.iter().cloned().map(|gate| gate.mapped(|wire| renumbered.get(&wire).copied().unwrap_or(wire))));
+
+        let mut header = self.header.clone();
+        header.num_gates = gates.len() as u32;
+        header.num_input_wires = remaining.iter().map(|(_, wires)| wires.len() as u32).collect();
+        header.input_types = self.header.input_types.as_ref()
+            .map(|types| remaining.iter().map(|&(port, _)| types[port]).collect());
+
+        let mut circuit = Circuit { header, gates };
+        circuit.fold_constants();
+        circuit.eliminate_dead_gates();
+        circuit.canonicalize();
+        Ok(circuit)
+    }
+
+    /// Translates the gates into operations for an arithmetic backend, which evaluates XOR gates as
+    /// additions and AND gates as multiplications mod 2. MAND gates are split into one multiplication
+    /// per output, the operations are in the stored order of the gates.
+    pub fn to_arithmetic_ops(&self) -> Vec<ArithOp> {
+        let mut ops = Vec::with_capacity(self.gates.len());
+        for gate in &self.gates {
+            match gate {
+                Gate::XOR { input_a, input_b, output } => ops.push(ArithOp::Add { input_a: *input_a, input_b: *input_b, output: *output }),
+                Gate::AND { input_a, input_b, output } => ops.push(ArithOp::Mul { input_a: *input_a, input_b: *input_b, output: *output }),
+                Gate::INV { input, output } => ops.push(ArithOp::Not { input: *input, output: *output }),
+                Gate::EQ { constant, output } => ops.push(ArithOp::Const { value: *constant, output: *output }),
+                Gate::EQW { input, output } => ops.push(ArithOp::Copy { input: *input, output: *output }),
+                Gate::MAND { inputs, outputs } => {
+                    let (inputs_a, inputs_b) = inputs.split_at(outputs.len());
+                    ops.extend(inputs_a.iter().zip(inputs_b).zip(outputs)
+                        .map(|((input_a, input_b), output)| ArithOp::Mul { input_a: *input_a, input_b: *input_b, output: *output }));
+                }
+            }
+        }
+        ops
+    }
+
+    /// Serializes the circuit into the bristol fashion format, s.t. parsing the result with
+    /// `CircuitFormat::Fashion` yields the same circuit
+    pub fn to_bristol(&self) -> String {
+        let mut bristol = self.bristol_header();
+        for gate in &self.gates {
+            let _ = writeln!(bristol, "{gate}");
+        }
+        bristol
+    }
+
+    /// Returns the header lines in the bristol fashion format, followed by the empty line separating
+    /// them from the gates
+    fn bristol_header(&self) -> String {
+        let header = &self.header;
+        format!(
+            "{} {}\n{} {}\n{} {}\n\n",
+            header.num_gates,
+            header.num_wires,
+            header.num_input_wires.len(),
+            join_wires(&header.num_input_wires),
+            header.num_output_wires.len(),
+            join_wires(&header.num_output_wires),
+        )
+    }
+
+    /// Writes the circuit in the bristol fashion format to the writer, one gate at a time instead of
+    /// building the whole file in memory first. The writer is buffered internally.
+    #[cfg(feature = "std")]
+    pub fn write<W: io::Write>(&self, writer: W) -> io::Result<()> {
+        // the module imports fmt::Write for writing to Strings, this is the io counterpart
+        use io::Write as _;
+        let mut writer = io::BufWriter::new(writer);
+
+        writer.write_all(self.bristol_header().as_bytes())?;
+        for gate in &self.gates {
+            writeln!(writer, "{gate}")?;
+        }
+        writer.flush()
+    }
+
+    /// Exports the circuit as a Graphviz DOT digraph, with one node per gate and per input/output port,
+    /// and one edge per wire from the producing gate or port to each consuming gate or port
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph circuit {\n");
+
+        // Map the wires of the input and output ports to the nodes of their ports
+        let mut sources: BTreeMap<WireId, String> = BTreeMap::new();
+        for (port, wires) in self.input_wire_indices().into_iter().enumerate() {
+            let _ = writeln!(dot, "    in{port} [label=\"input {port}\", shape=box];");
+            sources.extend(wires.into_iter().map(|wire| (wire, format!("in{port}"))));
+        }
+        let mut sinks: BTreeMap<WireId, String> = BTreeMap::new();
+        for (port, wires) in self.output_wire_indices().into_iter().enumerate() {
+            let _ = writeln!(dot, "    out{port} [label=\"output {port}\", shape=box];");
+            sinks.extend(wires.into_iter().map(|wire| (wire, format!("out{port}"))));
+        }
+
+        for (index, gate) in self.gates.iter().enumerate() {
+            let _ = writeln!(dot, "    g{index} [label=\"{}\"];", gate.type_name());
+            sources.extend(gate.outputs().into_iter().map(|wire| (wire, format!("g{index}"))));
+        }
+
+        // Undriven wires have no source node and are therefore not part of the graph
+        for (index, gate) in self.gates.iter().enumerate() {
+            for wire in gate.inputs() {
+                if let Some(source) = sources.get(&wire) {
+                    let _ = writeln!(dot, "    {source} -> g{index} [label=\"{wire}\"];");
+                }
+            }
+        }
+        for wire in self.output_wire_range().map(WireId) {
+            if let (Some(source), Some(sink)) = (sources.get(&wire), sinks.get(&wire)) {
+                let _ = writeln!(dot, "    {source} -> {sink} [label=\"{wire}\"];");
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Returns an infix boolean expression for each output wire (in the order of the output ports),
+    /// in terms of the input wires named `in<wire>` and the operators `^`, `&`, and `~`, e.g.
+    /// `(in0 ^ in1) & in2`. Operands are parenthesized unless they are single wires, constants,
+    /// negations, or use the same (associative) operator. Shared wires are expanded at every use, so
+    /// this fails once an expression exceeds `MAX_EXPRESSION_LEN`. Panics if the circuit contains a cycle.
+    pub fn output_expressions(&self) -> Result<Vec<String>, ExpressionTooLarge> {
+        // The expression of each assigned wire together with its top-level operator, if it is binary
+        let mut expressions: BTreeMap<WireId, (String, Option<char>)> = BTreeMap::new();
+        let expression = |expressions: &BTreeMap<WireId, (String, Option<char>)>, wire: WireId| {
+            let prefix = if self.input_wire_range().contains(&wire.0) { "in" } else { "w" };
+            expressions.get(&wire).cloned().unwrap_or_else(|| (format!("{prefix}{wire}"), None))
+        };
+        let operand = |(expression, operator): (String, Option<char>), parent: char| match operator {
+            Some(operator) if operator != parent => format!("({expression})"),
+            _ => expression,
+        };
+        let binary = |expressions: &BTreeMap<WireId, (String, Option<char>)>, a: WireId, b: WireId, operator: char| {
+            let (a, b) = (operand(expression(expressions, a), operator), operand(expression(expressions, b), operator));
+            (format!("{a} {operator} {b}"), Some(operator))
+        };
+
+        for index in self.topological_order().expect("expressions are only defined for acyclic circuits") {
+            let assigned = match &self.gates[index] {
+                Gate::XOR { input_a, input_b, output } => vec![(*output, binary(&expressions, *input_a, *input_b, '^'))],
+                Gate::AND { input_a, input_b, output } => vec![(*output, binary(&expressions, *input_a, *input_b, '&'))],
+                Gate::INV { input, output } => vec![(*output, (format!("~{}", operand(expression(&expressions, *input), '~')), None))],
+                Gate::EQ { constant, output } => vec![(*output, ((*constant as u8).to_string(), None))],
+                Gate::EQW { input, output } => vec![(*output, expression(&expressions, *input))],
+                Gate::MAND { inputs, outputs } => {
+                    let (inputs_a, inputs_b) = inputs.split_at(outputs.len());
+                    inputs_a.iter().zip(inputs_b).zip(outputs)
+                        .map(|((input_a, input_b), output)| (*output, binary(&expressions, *input_a, *input_b, '&')))
+                        .collect()
+                }
+            };
+            for (wire, (expression, operator)) in assigned {
+                if expression.len() > MAX_EXPRESSION_LEN {
+                    return Err(ExpressionTooLarge { wire });
+                }
+                expressions.insert(wire, (expression, operator));
+            }
+        }
+
+        Ok(self.output_wire_range().map(|wire| expression(&expressions, WireId(wire)).0).collect())
+    }
+
+    /// Exports the circuit as a structural Verilog module with the given name. The input ports are
+    /// named `in0`, `in1`, ... and the output ports `out0`, `out1`, ..., each as a vector with the
+    /// lowest wire as bit 0, while the remaining wires are declared as `w<wire>`.
+    /// Every gate becomes a continuous `assign`, so the module is purely combinational.
+    pub fn to_verilog(&self, module_name: &str) -> String {
+        let mut names: BTreeMap<WireId, String> = BTreeMap::new();
+        for (name, ports) in [("in", self.input_wire_indices()), ("out", self.output_wire_indices())] {
+            for (port, wires) in ports.iter().enumerate() {
+                names.extend(wires.iter().enumerate().map(|(bit, &wire)| (wire, format!("{name}{port}[{bit}]"))));
+            }
+        }
+        let name = |wire: WireId| names.get(&wire).cloned().unwrap_or_else(|| format!("w{wire}"));
+
+        let ports: Vec<String> = (0..self.header.num_input_wires.len()).map(|port| format!("in{port}"))
+            .chain((0..self.header.num_output_wires.len()).map(|port| format!("out{port}")))
+            .collect();
+        let mut verilog = format!("module {module_name}({});\n", ports.join(", "));
+        for (port, width) in self.header.num_input_wires.iter().enumerate() {
+            let _ = writeln!(verilog, "    input [{}:0] in{port};", width.saturating_sub(1));
+        }
+        for (port, width) in self.header.num_output_wires.iter().enumerate() {
+            let _ = writeln!(verilog, "    output [{}:0] out{port};", width.saturating_sub(1));
+        }
+        let internal: Vec<String> = (self.input_wire_range().end..self.output_wire_range().start).map(|wire| name(WireId(wire))).collect();
+        if !internal.is_empty() {
+            let _ = writeln!(verilog, "    wire {};", internal.join(", "));
+        }
+
+        for gate in &self.gates {
+            let _ = match gate {
+                Gate::XOR { input_a, input_b, output } =>
+                    writeln!(verilog, "    assign {} = {} ^ {};", name(*output), name(*input_a), name(*input_b)),
+                Gate::AND { input_a, input_b, output } =>
+                    writeln!(verilog, "    assign {} = {} & {};", name(*output), name(*input_a), name(*input_b)),
+                Gate::INV { input, output } => writeln!(verilog, "    assign {} = ~{};", name(*output), name(*input)),
+                Gate::EQ { constant, output } => writeln!(verilog, "    assign {} = 1'b{};", name(*output), *constant as u8),
+                Gate::EQW { input, output } => writeln!(verilog, "    assign {} = {};", name(*output), name(*input)),
+                Gate::MAND { inputs, outputs } => {
+                    let (inputs_a, inputs_b) = inputs.split_at(outputs.len());
+                    for ((input_a, input_b), output) in inputs_a.iter().zip(inputs_b).zip(outputs) {
+                        let _ = writeln!(verilog, "    assign {} = {} & {};", name(*output), name(*input_a), name(*input_b));
+                    }
+                    Ok(())
+                }
+            };
+        }
+
+        verilog.push_str("endmodule\n");
+        verilog
+    }
+
+    /// Exports the circuit as SMT-LIB boolean constraints, declaring a constant `w<wire>` for every
+    /// wire and asserting the function of each gate. The input wires are left unconstrained, while
+    /// each output wire is additionally named `out<port>_<bit>`, s.t. the outputs of two exported
+    /// circuits can be asserted to be equal (after renaming the wires of one of them).
+    pub fn to_smtlib(&self) -> String {
+        let mut smt = String::from("(set-logic QF_UF)\n");
+        for wire in 0..self.header.num_wires {
+            let _ = writeln!(smt, "(declare-const w{wire} Bool)");
+        }
+
+        let mut assert = |output: &WireId, term: String| {
+            let _ = writeln!(smt, "(assert (= w{output} {term}))");
+        };
+        for gate in &self.gates {
+            match gate {
+                Gate::XOR { input_a, input_b, output } => assert(output, format!("(xor w{input_a} w{input_b})")),
+                Gate::AND { input_a, input_b, output } => assert(output, format!("(and w{input_a} w{input_b})")),
+                Gate::INV { input, output } => assert(output, format!("(not w{input})")),
+                Gate::EQ { constant, output } => assert(output, constant.to_string()),
+                Gate::EQW { input, output } => assert(output, format!("w{input}")),
+                Gate::MAND { inputs, outputs } => {
+                    let (inputs_a, inputs_b) = inputs.split_at(outputs.len());
+                    for ((input_a, input_b), output) in inputs_a.iter().zip(inputs_b).zip(outputs) {
+                        assert(output, format!("(and w{input_a} w{input_b})"));
+                    }
+                }
+            }
+        }
+
+        for (port, wires) in self.output_wire_indices().iter().enumerate() {
+            for (bit, wire) in wires.iter().enumerate() {
+                let _ = writeln!(smt, "(define-fun out{port}_{bit} () Bool w{wire})");
+            }
+        }
+        smt
+    }
+
+    /// Evaluates the circuit in the clear on the provided inputs, with one Vec of bits per input port.
+    /// Returns one Vec of bits per output port.
+    /// The gates are evaluated in the stored order, so circuits that are not listed in topological
+    /// order need to be sorted with `topo_sort` first.
+    pub fn evaluate(&self, inputs: &[Vec<bool>]) -> Vec<Vec<bool>> {
+        let wires = self.evaluate_wires(inputs);
+        self.output_ports_of(|wire| wires[wire.index()])
+    }
+
+    /// Evaluates the circuit like `evaluate`, but additionally returns the value of every wire
+    /// that is an input wire or assigned by a gate, e.g. to print the intermediate signals
+    pub fn evaluate_traced(&self, inputs: &[Vec<bool>]) -> (Vec<Vec<bool>>, BTreeMap<WireId, bool>) {
+        let wires = self.evaluate_wires(inputs);
+        let trace = self.input_wire_range().map(WireId)
+            .chain(self.gates.iter().flat_map(|gate| gate.outputs()))
+            .map(|wire| (wire, wires[wire.index()]))
+            .collect();
+        (self.output_ports_of(|wire| wires[wire.index()]), trace)
+    }
+
+    /// Evaluates the gates in the stored order and returns the value of every wire
+    fn evaluate_wires<T: WireWord>(&self, inputs: &[Vec<T>]) -> Vec<T> {
+        let mut wires = vec![T::default(); self.header.num_wires as usize];
+        for (wire, value) in self.input_values(inputs) {
+            wires[wire.index()] = value;
+        }
+
+        for gate in &self.gates {
+            match gate {
+                Gate::XOR { input_a, input_b, output } =>
+                    wires[output.index()] = wires[input_a.index()] ^ wires[input_b.index()],
+                Gate::AND { input_a, input_b, output } =>
+                    wires[output.index()] = wires[input_a.index()] & wires[input_b.index()],
+                Gate::INV { input, output } =>
+                    wires[output.index()] = !wires[input.index()],
+                Gate::EQ { constant, output } =>
+                    wires[output.index()] = T::splat(*constant),
+                Gate::EQW { input, output } =>
+                    wires[output.index()] = wires[input.index()],
+                Gate::MAND { inputs, outputs } => {
+                    let (inputs_a, inputs_b) = inputs.split_at(outputs.len());
+                    for ((input_a, input_b), output) in inputs_a.iter().zip(inputs_b).zip(outputs) {
+                        wires[output.index()] = wires[input_a.index()] & wires[input_b.index()];
+                    }
+                }
+            }
+        }
+        wires
+    }
+
+    /// Checks that the inputs match the input ports and returns the value of each input wire.
+    /// The input ports are assigned to the lowest wires, in order.
+    fn input_values<'a, T: Copy>(&self, inputs: &'a [Vec<T>]) -> impl Iterator<Item=(WireId, T)> + 'a {
+        assert_eq!(inputs.len(), self.header.num_input_wires.len(), "Number of inputs must match the number of input ports");
+        for (input, &width) in inputs.iter().zip(&self.header.num_input_wires) {
+            assert_eq!(input.len(), width as usize, "Number of input bits must match the width of the input port");
+        }
+        (0..).map(WireId).zip(inputs.iter().flatten().copied())
+    }
+
+    /// Collects the values of the output wires into one Vec per output port.
+    /// The output ports are assigned to the highest wires, in order.
+    fn output_ports_of<T>(&self, value: impl Fn(WireId) -> T) -> Vec<Vec<T>> {
+        let mut wire = self.output_wire_range().start;
+        self.header.num_output_wires.iter()
+            .map(|&width| {
+                let output = (wire..wire + width).map(|wire| value(WireId(wire))).collect();
+                wire += width;
+                output
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "std")]
+    /// Evaluates the circuit like `evaluate`, but evaluates the independent gates of each layer
+    /// (see `layers`) concurrently on all available threads. The stored order of the gates does not
+    /// matter. Panics if the circuit contains a cycle.
+    pub fn evaluate_parallel(&self, inputs: &[Vec<bool>]) -> Vec<Vec<bool>> {
+        let mut wires = vec![false; self.header.num_wires as usize];
+        for (wire, value) in self.input_values(inputs) {
+            wires[wire.index()] = value;
+        }
+
+        let num_threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+        let mut outputs = Vec::new();
+        for layer in self.layers() {
+            // Spawning threads only pays off for layers with enough gates
+            if num_threads == 1 || layer.len() < MIN_GATES_PER_THREAD * 2 {
+                layer.iter().for_each(|&index| self.gates[index].evaluate_into(&wires, &mut outputs));
+            } else {
+                let chunk_size = layer.len().div_ceil(num_threads).max(MIN_GATES_PER_THREAD);
+                let wires = &wires;
+                outputs = thread::scope(|scope| {
+                    let handles: Vec<_> = layer.chunks(chunk_size)
+                        .map(|chunk| scope.spawn(move || {
+                            let mut outputs = Vec::new();
+                            chunk.iter().for_each(|&index| self.gates[index].evaluate_into(wires, &mut outputs));
+                            outputs
+                        }))
+                        .collect();
+                    handles.into_iter().flat_map(|handle| handle.join().expect("evaluation threads do not panic")).collect()
+                });
+            }
+            for (wire, value) in outputs.drain(..) {
+                wires[wire.index()] = value;
+            }
+        }
+        self.output_ports_of(|wire| wires[wire.index()])
+    }
+
+    /// Evaluates the circuit like `evaluate`, but only stores the values of wires that are still needed,
+    /// dropping each wire after its last consumer has been evaluated. This reduces the peak memory
+    /// usage for large circuits, where most wires are only needed for a short time.
+    pub fn evaluate_compact(&self, inputs: &[Vec<bool>]) -> Vec<Vec<bool>> {
+        // Wires that are never read can be dropped right away, unless they are outputs of the circuit
+        let mut last_uses: Vec<Vec<WireId>> = vec![Vec::new(); self.gates.len()];
+        for (wire, last_use) in self.wire_liveness() {
+            last_uses[last_use].push(wire);
+        }
+        let fan_out = self.fan_out(true);
+        let is_needed = |wire: &WireId| fan_out.contains_key(wire);
+
+        let mut wires: BTreeMap<WireId, bool> = self.input_values(inputs).filter(|(wire, _)| is_needed(wire)).collect();
+        let value = |wires: &BTreeMap<WireId, bool>, wire: WireId| wires.get(&wire).copied().unwrap_or(false);
+
+        for (index, gate) in self.gates.iter().enumerate() {
+            let outputs: Vec<(WireId, bool)> = match gate {
+                Gate::XOR { input_a, input_b, output } => vec![(*output, value(&wires, *input_a) ^ value(&wires, *input_b))],
+                Gate::AND { input_a, input_b, output } => vec![(*output, value(&wires, *input_a) & value(&wires, *input_b))],
+                Gate::INV { input, output } => vec![(*output, !value(&wires, *input))],
+                Gate::EQ { constant, output } => vec![(*output, *constant)],
+                Gate::EQW { input, output } => vec![(*output, value(&wires, *input))],
+                Gate::MAND { inputs, outputs } => {
+                    let (inputs_a, inputs_b) = inputs.split_at(outputs.len());
+                    inputs_a.iter().zip(inputs_b).zip(outputs)
+                        .map(|((input_a, input_b), output)| (*output, value(&wires, *input_a) & value(&wires, *input_b)))
+                        .collect()
+                }
+            };
+
+            for wire in &last_uses[index] {
+                wires.remove(wire);
+            }
+            wires.extend(outputs.into_iter().filter(|(wire, _)| is_needed(wire)));
+        }
+        self.output_ports_of(|wire| value(&wires, wire))
+    }
+
+    /// Evaluates the circuit on 64 independent sets of inputs at once, where the i-th bit of every u64
+    /// belongs to the i-th set of inputs. Each input port gets one u64 per wire, and each output port
+    /// yields one u64 per wire.
+    pub fn evaluate_packed(&self, inputs: &[Vec<u64>]) -> Vec<Vec<u64>> {
+        let wires = self.evaluate_wires(inputs);
+        self.output_ports_of(|wire| wires[wire.index()])
+    }
+
+    /// Returns the wires of each input port. The input ports occupy the lowest wires of the circuit, in order.
+    pub fn input_wire_indices(&self) -> Vec<Vec<WireId>> {
+        port_wires(self.input_wire_range().start, &self.header.num_input_wires)
+    }
+
+    /// Returns the wires of each output port. The output ports occupy the highest wires of the circuit, in order.
+    pub fn output_wire_indices(&self) -> Vec<Vec<WireId>> {
+        port_wires(self.output_wire_range().start, &self.header.num_output_wires)
+    }
+
+    /// Returns whether the wire belongs to an input port, an output port or is internal, together
+    /// with the index of its port (0 for internal wires). Returns None if the wire does not exist.
+    /// Wires belonging to both an input and an output port (of a circuit without gates) are inputs.
+    pub fn port_of_wire(&self, WireId(wire): WireId) -> Option<(PortKind, usize)> {
+        if wire >= self.header.num_wires {
+            return None;
+        }
+        let (inputs, outputs) = (self.input_wire_range(), self.output_wire_range());
+        Some(if inputs.contains(&wire) {
+            (PortKind::Input, port_at(&self.header.num_input_wires, wire - inputs.start))
+        } else if outputs.contains(&wire) {
+            (PortKind::Output, port_at(&self.header.num_output_wires, wire - outputs.start))
+        } else {
+            (PortKind::Internal, 0)
+        })
+    }
+
+    /// Splits one integer per input port into the bits of the port, with the least significant bit
+    /// on the lowest wire. The result can be passed to `evaluate`.
+    pub fn pack_inputs(&self, values: &[u64]) -> Vec<Vec<bool>> {
+        assert_eq!(values.len(), self.header.num_input_wires.len(), "Number of values must match the number of input ports");
+        values.iter().zip(&self.header.num_input_wires)
+            .map(|(&value, &width)| (0..width).map(|bit| value.checked_shr(bit).unwrap_or(0) & 1 == 1).collect())
+            .collect()
+    }
+
+    /// Combines the bits of each output port (as returned by `evaluate`) into an integer, with the
+    /// least significant bit on the lowest wire. Signed output ports are sign-extended to 64 bits.
+    pub fn unpack_outputs(&self, bits: &[Vec<bool>]) -> Vec<u64> {
+        assert_eq!(bits.len(), self.header.num_output_wires.len(), "Number of outputs must match the number of output ports");
+        bits.iter().enumerate()
+            .map(|(port, bits)| {
+                assert!(bits.len() <= 64, "Output ports with more than 64 wires do not fit into an integer");
+                let value = bits.iter().rev().fold(0, |value, &bit| value << 1 | bit as u64);
+                match (self.header.output_type(port), bits.last()) {
+                    (Some(PortType::SignedInt), Some(true)) => value | u64::MAX.checked_shl(bits.len() as u32).unwrap_or(0),
+                    _ => value,
+                }
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "std")]
+    /// Compares the circuit against a reference implementation on the given number of random inputs.
+    /// The oracle gets all input bits of the circuit (in the order of the input ports) and needs to
+    /// return all output bits (in the order of the output ports).
+    /// Returns the first input on which the circuit and the oracle disagree.
+    pub fn check_against<F: Fn(&[bool]) -> Vec<bool>>(&self, oracle: F, samples: usize) -> Result<(), Mismatch> {
+        let state = RandomState::new();
+        let num_input_wires = self.input_wire_range().len();
+
+        for sample in 0..samples {
+            let inputs: Vec<bool> = (0..num_input_wires)
+                .map(|wire| state.hash_one((sample, wire)) & 1 == 1)
+                .collect();
+
+            let got: Vec<bool> = self.evaluate(&self.split_input_ports(&inputs)).concat();
+            let expected = oracle(&inputs);
+            if got != expected {
+                return Err(Mismatch { inputs, expected, got });
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates the circuit on every assignment of its input wires and returns the pairs of all
+    /// input bits and all output bits (each in the order of the ports). In the i-th row, the input
+    /// wire `w` holds bit `w` of i, so the rows count up with the first input wire as the least
+    /// significant bit. Fails for circuits with more than `MAX_TRUTH_TABLE_INPUTS` input wires.
+    pub fn truth_table(&self) -> Result<TruthTable, TooManyInputs> {
+        let num_input_wires = self.input_wire_range().len();
+        if num_input_wires > MAX_TRUTH_TABLE_INPUTS {
+            return Err(TooManyInputs { inputs: num_input_wires });
+        }
+
+        Ok((0..1u32 << num_input_wires)
+            .map(|row| {
+                let inputs: Vec<bool> = (0..num_input_wires).map(|wire| row >> wire & 1 == 1).collect();
+                let outputs = self.evaluate(&self.split_input_ports(&inputs)).concat();
+                (inputs, outputs)
+            })
+            .collect())
+    }
+
+    /// Returns whether both circuits compute the same function, i.e. have the same widths of input and
+    /// output ports and the same outputs on every input. The circuits are evaluated exhaustively,
+    /// which fails for circuits with more than `MAX_TRUTH_TABLE_INPUTS` input wires.
+    pub fn equivalent_to(&self, other: &Circuit) -> Result<bool, TooManyInputs> {
+        if self.header.num_input_wires != other.header.num_input_wires || self.header.num_output_wires != other.header.num_output_wires {
+            return Ok(false);
+        }
+        let num_input_wires = self.input_wire_range().len();
+        if num_input_wires > MAX_TRUTH_TABLE_INPUTS {
+            return Err(TooManyInputs { inputs: num_input_wires });
+        }
+
+        Ok((0..1u32 << num_input_wires).all(|row| {
+            let inputs: Vec<bool> = (0..num_input_wires).map(|wire| row >> wire & 1 == 1).collect();
+            let inputs = self.split_input_ports(&inputs);
+            self.evaluate(&inputs) == other.evaluate(&inputs)
+        }))
+    }
+
+    /// Splits the bits of all input wires into one Vec per input port
+    fn split_input_ports(&self, inputs: &[bool]) -> Vec<Vec<bool>> {
+        let mut wire = 0;
+        self.header.num_input_wires.iter()
+            .map(|&width| {
+                let port = inputs[wire..wire + width as usize].to_vec();
+                wire += width as usize;
+                port
+            })
+            .collect()
+    }
+
+    /// Simulates the evaluation of the circuit by two parties A and B in the GMW protocol, given the
+    /// XOR-shares of the input wires of each party. XOR and INV gates are evaluated locally on the
+    /// shares, while every AND consumes one Beaver triple `(a, b, c)` with `c = a & b`.
+    /// Each triple is shared by giving party A the triple and party B zeros, which is correct but
+    /// obviously not secure, as the simulation only exercises the computation on the shares.
+    /// Returns the shares of the output wires of party A and party B.
+    pub fn evaluate_shared(
+        &self,
+        shares_a: &[bool],
+        shares_b: &[bool],
+        and_triples: &mut impl Iterator<Item=(bool, bool, bool)>,
+    ) -> (Vec<bool>, Vec<bool>) {
+        let num_input_wires = self.input_wire_range().end as usize;
+        assert_eq!(shares_a.len(), num_input_wires, "Party A needs one share per input wire");
+        assert_eq!(shares_b.len(), num_input_wires, "Party B needs one share per input wire");
+
+        let mut wires_a = vec![false; self.header.num_wires as usize];
+        let mut wires_b = vec![false; self.header.num_wires as usize];
+        wires_a[..num_input_wires].copy_from_slice(shares_a);
+        wires_b[..num_input_wires].copy_from_slice(shares_b);
+
+        // Computes the shares of x & y from the shares of x and y and a Beaver triple
+        let mut and = |x: (bool, bool), y: (bool, bool)| {
+            let (a, b, c) = and_triples.next().expect("every AND needs a multiplication triple");
+            let ((a_a, b_a, c_a), (a_b, b_b, c_b)) = ((a, b, c), (false, false, false));
+            // the masked inputs d = x ^ a and e = y ^ b are opened by exchanging the shares
+            let d = (x.0 ^ a_a) ^ (x.1 ^ a_b);
+            let e = (y.0 ^ b_a) ^ (y.1 ^ b_b);
+            (c_a ^ (d & b_a) ^ (e & a_a) ^ (d & e), c_b ^ (d & b_b) ^ (e & a_b))
+        };
+
+        for gate in &self.gates {
+            match gate {
+                Gate::XOR { input_a, input_b, output } => {
+                    wires_a[output.index()] = wires_a[input_a.index()] ^ wires_a[input_b.index()];
+                    wires_b[output.index()] = wires_b[input_a.index()] ^ wires_b[input_b.index()];
+                }
+                Gate::AND { input_a, input_b, output } => {
+                    let x = (wires_a[input_a.index()], wires_b[input_a.index()]);
+                    let y = (wires_a[input_b.index()], wires_b[input_b.index()]);
+                    (wires_a[output.index()], wires_b[output.index()]) = and(x, y);
+                }
+                // only one party needs to flip its share to negate the shared value
+                Gate::INV { input, output } => {
+                    wires_a[output.index()] = !wires_a[input.index()];
+                    wires_b[output.index()] = wires_b[input.index()];
+                }
+                Gate::EQ { constant, output } => {
+                    wires_a[output.index()] = *constant;
+                    wires_b[output.index()] = false;
+                }
+                Gate::EQW { input, output } => {
+                    wires_a[output.index()] = wires_a[input.index()];
+                    wires_b[output.index()] = wires_b[input.index()];
+                }
+                Gate::MAND { inputs, outputs } => {
+                    let (inputs_a, inputs_b) = inputs.split_at(outputs.len());
+                    for ((input_a, input_b), output) in inputs_a.iter().zip(inputs_b).zip(outputs) {
+                        let x = (wires_a[input_a.index()], wires_b[input_a.index()]);
+                        let y = (wires_a[input_b.index()], wires_b[input_b.index()]);
+                        (wires_a[output.index()], wires_b[output.index()]) = and(x, y);
+                    }
+                }
+            }
+        }
+
+        let outputs = self.output_wire_range();
+        (wires_a[outputs.start as usize..].to_vec(), wires_b[outputs.start as usize..].to_vec())
+    }
+
+    /// Reorders the gates s.t. every gate comes after the gates producing its input wires.
+    /// Gates that are already in a valid order keep their relative order.
+    pub fn topo_sort(&mut self) -> Result<(), CycleError> {
+        let order = self.topological_order()?;
+
+        let mut gates: Vec<Option<Gate>> = mem::take(&mut self.gates).into_iter().map(Some).collect();
+        self.gates = order.into_iter()
+            .map(|index| gates[index].take().expect("topological order contains every gate exactly once"))
+            .collect();
+        Ok(())
+    }
+
+    /// Returns the gates in topological order without reordering the stored gates, like `topo_sort` would.
+    /// Fails if the circuit contains a cycle.
+    pub fn gates_topological(&self) -> Result<impl Iterator<Item=&Gate>, CycleError> {
+        let order = self.topological_order()?;
+        Ok(order.into_iter().map(|index| &self.gates[index]))
+    }
+
+    /// Returns whether the circuit contains a combinational cycle, i.e. a wire that depends on itself
+    pub fn has_cycle(&self) -> bool {
+        self.find_cycle().is_some()
+    }
+
+    /// Returns the wires along a combinational cycle of the circuit, if there is one.
+    /// Each returned wire is produced by a gate reading the next wire in the Vec, and the last wire
+    /// is produced by a gate reading the first one.
+    pub fn find_cycle(&self) -> Option<Vec<WireId>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum State { OnPath, Done }
+
+        let producers = self.producers();
+        let mut states: BTreeMap<WireId, State> = BTreeMap::new();
+
+        // Depth-first search from every produced wire, following each wire to the inputs of its producing gate
+        for start in self.gates.iter().flat_map(Gate::outputs) {
+            if states.contains_key(&start) {
+                continue;
+            }
+            states.insert(start, State::OnPath);
+            let mut path: Vec<(WireId, Vec<WireId>)> = vec![(start, self.gates[producers[&start]].inputs())];
+
+            while let Some((_, remaining_inputs)) = path.last_mut() {
+                let Some(input) = remaining_inputs.pop() else {
+                    let (wire, _) = path.pop().expect("path is not empty");
+                    states.insert(wire, State::Done);
+                    continue;
+                };
+                match (states.get(&input), producers.get(&input)) {
+                    (Some(State::OnPath), _) => {
+                        let cycle_start = path.iter().position(|&(wire, _)| wire == input).expect("wire is on the path");
+                        return Some(path[cycle_start..].iter().map(|&(wire, _)| wire).collect());
+                    }
+                    (None, Some(&producer)) => {
+                        states.insert(input, State::OnPath);
+                        path.push((input, self.gates[producer].inputs()));
+                    }
+                    // Circuit inputs and already explored wires can not be part of a new cycle
+                    _ => {}
+                }
+            }
+        }
+        None
+    }
+
+    /// Groups the indices of the gates into layers, s.t. every gate only reads circuit inputs and
+    /// outputs of gates in earlier layers, and places each gate into the earliest possible layer
+    /// (an ASAP schedule). Panics if the circuit contains a cycle.
+    pub fn layers(&self) -> Vec<Vec<usize>> {
+        let order = self.topological_order().expect("layers are only defined for acyclic circuits");
+
+        // The first layer reading each wire, which is 0 for circuit inputs
+        let mut wire_layers = vec![0usize; self.header.num_wires as usize];
+        let mut layers: Vec<Vec<usize>> = Vec::new();
+        for index in order {
+            let gate = &self.gates[index];
+            let layer = gate.inputs().iter().map(|&wire| wire_layers[wire.index()]).max().unwrap_or(0);
+            for output in gate.outputs() {
+                wire_layers[output.index()] = layer + 1;
+            }
+            if layers.len() <= layer {
+                layers.resize(layer + 1, Vec::new());
+            }
+            layers[layer].push(index);
+        }
+        layers.iter_mut().for_each(|layer| layer.sort_unstable());
+        layers
+    }
+
+    /// Maps each wire to the gate producing it, wires without a producing gate are circuit inputs
+    fn producers(&self) -> BTreeMap<WireId, usize> {
+        let mut producers: BTreeMap<WireId, usize> = BTreeMap::new();
+        for (index, gate) in self.gates.iter().enumerate() {
+            for output in gate.outputs() {
+                producers.insert(output, index);
+            }
+        }
+        producers
+    }
+
+    /// Computes the indices of the gates in topological order, preferring the gate listed first
+    /// whenever multiple gates could be evaluated next
+    fn topological_order(&self) -> Result<Vec<usize>, CycleError> {
+        let producers = self.producers();
+
+        // For each gate, count the input wires that are produced by other gates,
+        // and remember which gates consume the outputs of each gate
+        let mut num_pending_inputs = vec![0usize; self.gates.len()];
+        let mut consumers: Vec<Vec<usize>> = vec![Vec::new(); self.gates.len()];
+        for (index, gate) in self.gates.iter().enumerate() {
+            for input in gate.inputs() {
+                if let Some(&producer) = producers.get(&input) {
+                    num_pending_inputs[index] += 1;
+                    consumers[producer].push(index);
+                }
+            }
+        }
+
+        let mut ready: BinaryHeap<Reverse<usize>> = (0..self.gates.len())
+            .filter(|&index| num_pending_inputs[index] == 0)
+            .map(Reverse)
+            .collect();
+        let mut order = Vec::with_capacity(self.gates.len());
+        while let Some(Reverse(index)) = ready.pop() {
+            order.push(index);
+            for &consumer in &consumers[index] {
+                num_pending_inputs[consumer] -= 1;
+                if num_pending_inputs[consumer] == 0 {
+                    ready.push(Reverse(consumer));
+                }
+            }
+        }
+
+        // Gates that never became ready are part of, or depend on, a cycle
+        match num_pending_inputs.iter().position(|&pending| pending > 0) {
+            Some(gate) => Err(CycleError { gate }),
+            None => Ok(order),
+        }
+    }
+}
+
+/// Indexes into the gates of the circuit in their stored order, like `circuit.gates()[index]`
+impl Index<usize> for Circuit {
+    type Output = Gate;
+
+    fn index(&self, index: usize) -> &Gate {
+        &self.gates[index]
+    }
+}
+
+/// Consumes the circuit and yields its gates in their stored order
+impl IntoIterator for Circuit {
+    type Item = Gate;
+    type IntoIter = alloc::vec::IntoIter<Gate>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.gates.into_iter()
+    }
+}
+
+/// Yields the gates of the circuit in their stored order, like `circuit.gates().iter()`
+impl<'a> IntoIterator for &'a Circuit {
+    type Item = &'a Gate;
+    type IntoIter = core::slice::Iter<'a, Gate>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.gates.iter()
+    }
+}
+
+/// Hashes the value with the FNV-1a hash, which (unlike the hashers of the std library) is fixed
+/// and available without std, s.t. fingerprints are the same on every platform and run
+fn hash_of(value: impl Hash) -> u64 {
+    let mut hasher = Fnv1a(0xcbf2_9ce4_8422_2325);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The 64 bit FNV-1a hasher, holding the hash of the bytes written so far
+struct Fnv1a(u64);
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Converts a required number of wires into a wire count, failing if it does not fit into a u32
+fn wire_count(required: u64) -> Result<u32, WireOverflow> {
+    u32::try_from(required).map_err(|_| WireOverflow { required })
+}
+
+/// Returns the index of the port containing the wire at the offset from the first wire of the ports,
+/// which must be below the summed widths of the ports
+fn port_at(widths: &[u32], offset: u32) -> usize {
+    let mut end = 0;
+    widths.iter()
+        .position(|&width| {
+            end += width;
+            offset < end
+        })
+        .expect("offset lies within the ports")
+}
+
+/// Splits the consecutive wires starting at the given wire into ports of the given widths
+fn port_wires(start: u32, widths: &[u32]) -> Vec<Vec<WireId>> {
+    let mut wire = start;
+    widths.iter()
+        .map(|&width| {
+            let port = (wire..wire + width).map(WireId).collect();
+            wire += width;
+            port
+        })
+        .collect()
+}
+
+/// Value of a wire during the evaluation of a circuit, either a single bit or a word of
+/// independent bits evaluated at once by `Circuit::evaluate_packed`
+trait WireWord: Copy + Default + BitXor<Output=Self> + BitAnd<Output=Self> + Not<Output=Self> {
+    /// Returns the word with every bit set to the constant
+    fn splat(constant: bool) -> Self;
+}
+
+impl WireWord for bool {
+    fn splat(constant: bool) -> Self {
+        constant
+    }
+}
+
+impl WireWord for u64 {
+    fn splat(constant: bool) -> Self {
+        if constant { u64::MAX } else { 0 }
+    }
+}
+
+/// Callbacks for processing a circuit while it is parsed by `Circuit::parse_streaming`,
+/// which first passes the header and then every gate in the order of the file
+pub trait GateVisitor {
+    fn header(&mut self, header: &Header);
+    fn gate(&mut self, gate: &Gate);
+}
+
+/// Receiver of the parsed header and gates, which takes ownership of the gates unlike a `GateVisitor`
+trait GateSink {
+    fn header(&mut self, header: &Header);
+    fn gate(&mut self, gate: Gate);
+
+    /// Receives the annotation of the latest gate, if the gate line has one
+    fn annotation(&mut self, _annotation: &str) {}
+}
+
+/// Collects the gates of a circuit
+impl GateSink for Vec<Gate> {
+    fn header(&mut self, header: &Header) {
+        // The declared number of gates may be wrong, so it is only a hint and bounded
+        // to not reserve huge amounts of memory for a bogus header
+        self.reserve((header.num_gates as usize).min(MAX_RESERVED_GATES));
+    }
+
+    fn gate(&mut self, gate: Gate) {
+        self.push(gate);
+    }
+}
+
+/// Collects the gates of a circuit together with their annotations, keyed by the index of the gate
+#[derive(Default)]
+struct Annotated {
+    gates: Vec<Gate>,
+    annotations: BTreeMap<usize, String>,
+}
+
+impl GateSink for Annotated {
+    fn header(&mut self, header: &Header) {
+        self.gates.header(header);
+    }
+
+    fn gate(&mut self, gate: Gate) {
+        self.gates.push(gate);
+    }
+
+    fn annotation(&mut self, annotation: &str) {
+        self.annotations.insert(self.gates.len() - 1, annotation.to_string());
+    }
+}
+
+/// Passes the header and gates on to a visitor
+#[cfg(feature = "std")]
+struct Visiting<'a, V>(&'a mut V);
+
+#[cfg(feature = "std")]
+impl<V: GateVisitor> GateSink for Visiting<'_, V> {
+    fn header(&mut self, header: &Header) {
+        self.0.header(header);
+    }
+
+    fn gate(&mut self, gate: Gate) {
+        self.0.gate(&gate);
+    }
+}
+
+/// Value of a wire as far as it is known before evaluating the circuit
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WireValue {
+    Constant(bool),
+    // the wire carries the same value as the contained wire
+    Wire(WireId),
+}
+
+// Optimization passes, which all preserve the function computed by the circuit
+impl Circuit {
+    /// Propagates the constants assigned by EQ gates through the circuit, e.g. an AND gate with a
+    /// constant 0 input becomes constant 0, and a XOR gate with a constant 1 input becomes an INV gate.
+    /// Gates which become trivial are removed and later gates read the original wires instead,
+    /// and output wires with a constant value are assigned by EQ gates.
+    /// The resulting gates are in topological order. Panics if the circuit contains a cycle.
+    pub fn fold_constants(&mut self) {
+        let order = self.topological_order().expect("constants can only be folded in acyclic circuits");
+        let mut gates = mem::take(&mut self.gates).into_iter().map(Some).collect::<Vec<_>>();
+
+        // Wires without an entry carry their own value
+        let mut values: BTreeMap<WireId, WireValue> = BTreeMap::new();
+        let value = |values: &BTreeMap<WireId, WireValue>, wire: WireId| values.get(&wire).copied().unwrap_or(WireValue::Wire(wire));
+
+        for gate in order.into_iter().map(|index| gates[index].take().expect("every gate is visited once")) {
+            match gate {
+                Gate::XOR { input_a, input_b, output } => match (value(&values, input_a), value(&values, input_b)) {
+                    (WireValue::Constant(a), WireValue::Constant(b)) => { values.insert(output, WireValue::Constant(a ^ b)); }
+                    (WireValue::Constant(false), WireValue::Wire(wire)) | (WireValue::Wire(wire), WireValue::Constant(false)) => {
+                        values.insert(output, WireValue::Wire(wire));
+                    }
+                    (WireValue::Constant(true), WireValue::Wire(input)) | (WireValue::Wire(input), WireValue::Constant(true)) =>
+                        self.gates.push(Gate::INV { input, output }),
+                    (WireValue::Wire(a), WireValue::Wire(b)) if a == b => { values.insert(output, WireValue::Constant(false)); }
+                    (WireValue::Wire(input_a), WireValue::Wire(input_b)) => self.gates.push(Gate::XOR { input_a, input_b, output }),
+                },
+                Gate::AND { input_a, input_b, output } => match fold_and(value(&values, input_a), value(&values, input_b)) {
+                    Some(folded) => { values.insert(output, folded); }
+                    None => self.gates.push(Gate::AND { input_a: resolve(&values, input_a), input_b: resolve(&values, input_b), output }),
+                },
+                Gate::INV { input, output } => match value(&values, input) {
+                    WireValue::Constant(constant) => { values.insert(output, WireValue::Constant(!constant)); }
+                    WireValue::Wire(input) => self.gates.push(Gate::INV { input, output }),
+                },
+                Gate::EQ { constant, output } => { values.insert(output, WireValue::Constant(constant)); }
+                Gate::EQW { input, output } => { values.insert(output, value(&values, input)); }
+                Gate::MAND { inputs, outputs } => {
+                    // Only the ANDs that can not be folded remain part of the MAND gate
+                    let (inputs_a, inputs_b) = inputs.split_at(outputs.len());
+                    let mut remaining: Vec<(WireId, WireId, WireId)> = Vec::new();
+                    for ((&input_a, &input_b), &output) in inputs_a.iter().zip(inputs_b).zip(&outputs) {
+                        match fold_and(value(&values, input_a), value(&values, input_b)) {
+                            Some(folded) => { values.insert(output, folded); }
+                            None => remaining.push((resolve(&values, input_a), resolve(&values, input_b), output)),
+                        }
+                    }
+                    match remaining[..] {
+                        [] => {}
+                        [(input_a, input_b, output)] => self.gates.push(Gate::AND { input_a, input_b, output }),
+                        _ => self.gates.push(Gate::MAND {
+                            inputs: remaining.iter().map(|&(a, _, _)| a).chain(remaining.iter().map(|&(_, b, _)| b)).collect(),
+                            outputs: remaining.iter().map(|&(_, _, output)| output).collect(),
+                        }),
+                    }
+                }
+            }
+        }
+
+        // Remaining gates need to read the wires the folded gates passed through
+        for gate in &mut self.gates {
+            gate.map_inputs(|wire| resolve(&values, wire));
+        }
+
+        // Output wires must still be assigned, even if their gates were folded
+        for wire in self.output_wire_range().map(WireId) {
+            match value(&values, wire) {
+                WireValue::Constant(constant) => self.gates.push(Gate::EQ { constant, output: wire }),
+                WireValue::Wire(input) if input != wire => self.gates.push(Gate::EQW { input, output: wire }),
+                WireValue::Wire(_) => {}
+            }
+        }
+        self.header.num_gates = self.gates.len() as u32;
+    }
+
+    /// Removes all gates whose outputs do not (transitively) contribute to any output wire of the circuit
+    pub fn eliminate_dead_gates(&mut self) {
+        let producers = self.producers();
+
+        // Backward search from the output wires over the inputs of the producing gates
+        let mut live_gates = vec![false; self.gates.len()];
+        let mut pending: Vec<WireId> = self.output_wire_range().map(WireId).collect();
+        while let Some(wire) = pending.pop() {
+            if let Some(&producer) = producers.get(&wire) {
+                if !live_gates[producer] {
+                    live_gates[producer] = true;
+                    pending.extend(self.gates[producer].inputs());
+                }
+            }
+        }
+
+        let mut live_gates = live_gates.into_iter();
+        self.gates.retain(|_| live_gates.next().expect("one entry per gate"));
+        self.header.num_gates = self.gates.len() as u32;
+    }
+
+    /// Removes gates computing the same function of the same input wires as an earlier gate, and lets
+    /// all gates reading the outputs of a removed gate read the outputs of the earlier gate instead.
+    /// XOR and AND gates are considered identical regardless of the order of their inputs.
+    /// The resulting gates are in topological order. Panics if the circuit contains a cycle.
+    pub fn deduplicate_gates(&mut self) {
+        let order = self.topological_order().expect("gates can only be deduplicated in acyclic circuits");
+        let mut gates = mem::take(&mut self.gates).into_iter().map(Some).collect::<Vec<_>>();
+        let circuit_outputs = self.output_wire_range();
+
+        // Maps the type and inputs of each distinct gate to its outputs
+        let mut canonical_outputs: BTreeMap<(&'static str, Vec<WireId>), Vec<WireId>> = BTreeMap::new();
+        let mut replacements: BTreeMap<WireId, WireId> = BTreeMap::new();
+
+        for mut gate in order.into_iter().map(|index| gates[index].take().expect("every gate is visited once")) {
+            gate.map_inputs(|wire| replacements.get(&wire).copied().unwrap_or(wire));
+            let mut inputs = gate.inputs();
+            match gate {
+                Gate::XOR { .. } | Gate::AND { .. } => inputs.sort_unstable(),
+                Gate::EQ { constant, .. } => inputs.push(WireId(constant as u32)),
+                _ => {}
+            }
+
+            match canonical_outputs.entry((gate.type_name(), inputs)) {
+                Entry::Occupied(entry) => {
+                    for (duplicate, &canonical) in gate.outputs().into_iter().zip(entry.get()) {
+                        replacements.insert(duplicate, canonical);
+                        // Output wires of the circuit need to stay assigned, so they become a copy instead
+                        if circuit_outputs.contains(&duplicate.0) {
+                            self.gates.push(Gate::EQW { input: canonical, output: duplicate });
+                        }
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(gate.outputs());
+                    self.gates.push(gate);
+                }
+            }
+        }
+        self.header.num_gates = self.gates.len() as u32;
+    }
+
+    /// Removes pairs of INV gates where the output of the first INV is only read by the second one,
+    /// and lets all gates reading the output of the second INV read the input of the first INV instead.
+    /// If the second INV assigns an output wire of the circuit, it becomes a copy of that input instead.
+    /// Returns the number of removed pairs, e.g. to warn about composition artifacts.
+    /// The resulting gates are in topological order. Panics if the circuit contains a cycle.
+    pub fn remove_double_inv(&mut self) -> usize {
+        let order = self.topological_order().expect("double INVs can only be removed in acyclic circuits");
+        let producers = self.producers();
+        let fan_out = self.fan_out(true);
+        let circuit_outputs = self.output_wire_range();
+
+        // Maps the second INV of each pair to the (replaced) input of the first INV
+        let mut second_invs: BTreeMap<usize, WireId> = BTreeMap::new();
+        let mut removed = vec![false; self.gates.len()];
+        let mut replacements: BTreeMap<WireId, WireId> = BTreeMap::new();
+        for &index in &order {
+            let Gate::INV { input: middle, output } = self.gates[index] else { continue };
+            let Some(&first) = producers.get(&middle) else { continue };
+            let Gate::INV { input, .. } = self.gates[first] else { continue };
+            if removed[first] || fan_out.get(&middle) != Some(&1) {
+                continue;
+            }
+
+            let input = replacements.get(&input).copied().unwrap_or(input);
+            removed[first] = true;
+            removed[index] = true;
+            second_invs.insert(index, input);
+            replacements.insert(output, input);
+        }
+
+        let mut gates = mem::take(&mut self.gates).into_iter().map(Some).collect::<Vec<_>>();
+        for index in order {
+            let mut gate = gates[index].take().expect("every gate is visited once");
+            match second_invs.get(&index) {
+                // Output wires of the circuit need to stay assigned, so they become a copy instead
+                Some(&input) => if let Gate::INV { output, .. } = gate {
+                    if circuit_outputs.contains(&output.0) {
+                        self.gates.push(Gate::EQW { input, output });
+                    }
+                },
+                None if !removed[index] => {
+                    gate.map_inputs(|wire| replacements.get(&wire).copied().unwrap_or(wire));
+                    self.gates.push(gate);
+                }
+                None => {}
+            }
+        }
+        self.header.num_gates = self.gates.len() as u32;
+        second_invs.len()
+    }
+
+    /// Collapses the regions of XOR, INV, EQ, and EQW gates into a canonical form. Every wire leaving a
+    /// linear region (as an output wire of the circuit or an input of an AND or MAND) is recomputed as
+    /// a single chain of XORs over the wires entering the region it depends on an odd number of
+    /// times, followed by an INV if its parity is inverted. Wires the region depends on an even number
+    /// of times cancel out, and intermediate wires only read inside the region are removed. The wires
+    /// are compacted afterwards and the resulting gates are in topological order.
+    /// Panics if the circuit contains a cycle.
+    pub fn linearize_xor_regions(&mut self) {
+        enum Planned {
+            Gate(Gate),
+            Parity { output: WireId, wires: Vec<WireId>, inverted: bool },
+        }
+
+        let order = self.topological_order().expect("linear regions are only defined for acyclic circuits");
+        let circuit_outputs = self.output_wire_range();
+        let nonlinear_inputs: BTreeSet<WireId> = self.gates.iter().filter(|gate| gate.is_nonlinear()).flat_map(Gate::inputs).collect();
+
+        // The parity of each wire assigned by a linear gate, as the sorted wires entering its region
+        // and whether the parity is inverted
+        let mut parities: BTreeMap<WireId, (Vec<WireId>, bool)> = BTreeMap::new();
+        let parity = |parities: &BTreeMap<WireId, (Vec<WireId>, bool)>, wire: WireId| {
+            parities.get(&wire).cloned().unwrap_or_else(|| (vec![wire], false))
+        };
+        let mut planned = Vec::new();
+        // intermediate wires of the XOR chains, which are placed below the output wires
+        let mut num_chain_wires = 0;
+        for index in order {
+            let (output, (wires, inverted)) = match &self.gates[index] {
+                Gate::XOR { input_a, input_b, output } => {
+                    let ((wires_a, inverted_a), (wires_b, inverted_b)) = (parity(&parities, *input_a), parity(&parities, *input_b));
+                    (*output, (xor_parities(&wires_a, &wires_b), inverted_a ^ inverted_b))
+                }
+                Gate::INV { input, output } => {
+                    let (wires, inverted) = parity(&parities, *input);
+                    (*output, (wires, !inverted))
+                }
+                Gate::EQW { input, output } => (*output, parity(&parities, *input)),
+                Gate::EQ { constant, output } => (*output, (Vec::new(), *constant)),
+                gate => {
+                    planned.push(Planned::Gate(gate.clone()));
+                    continue;
+                }
+            };
+            if circuit_outputs.contains(&output.0) || nonlinear_inputs.contains(&output) {
+                if wires.len() >= 2 {
+                    num_chain_wires += wires.len() - 2 + inverted as usize;
+                }
+                planned.push(Planned::Parity { output, wires: wires.clone(), inverted });
+            }
+            parities.insert(output, (wires, inverted));
+        }
+
+        let num_wires = wire_count(self.header.num_wires as u64 + num_chain_wires as u64)
+            .expect("the XOR chains need more wires than can be indexed");
+        let shift = |wire: WireId| if wire.0 >= circuit_outputs.start { wire + num_chain_wires as u32 } else { wire };
+        let mut next_chain_wire = WireId(circuit_outputs.start);
+        self.gates = Vec::new();
+        for planned in planned {
+            let (output, wires, inverted) = match planned {
+                Planned::Gate(gate) => {
+                    self.gates.push(gate.mapped(shift));
+                    continue;
+                }
+                Planned::Parity { output, wires, inverted } => (shift(output), wires, inverted),
+            };
+            match wires[..] {
+                [] => self.gates.push(Gate::EQ { constant: inverted, output }),
+                [wire] if inverted => self.gates.push(Gate::INV { input: shift(wire), output }),
+                [wire] => self.gates.push(Gate::EQW { input: shift(wire), output }),
+                _ => {
+                    let mut parity = shift(wires[0]);
+                    for (position, &wire) in wires.iter().enumerate().skip(1) {
+                        let chain_output = if position == wires.len() - 1 && !inverted {
+                            output
+                        } else {
+                            next_chain_wire += 1;
+                            next_chain_wire - 1
+                        };
+                        self.gates.push(Gate::XOR { input_a: parity, input_b: shift(wire), output: chain_output });
+                        parity = chain_output;
+                    }
+                    if inverted {
+                        self.gates.push(Gate::INV { input: parity, output });
+                    }
+                }
+            }
+        }
+        self.header.num_gates = self.gates.len() as u32;
+        self.header.num_wires = num_wires;
+        self.canonicalize();
+    }
+}
+
+/// XORs two parities given as sorted sets of wires, i.e. computes the symmetric difference of the sets
+fn xor_parities(a: &[WireId], b: &[WireId]) -> Vec<WireId> {
+    let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+    let mut parity = Vec::new();
+    loop {
+        match (a.peek(), b.peek()) {
+            (Some(wire_a), Some(wire_b)) if wire_a == wire_b => {
+                a.next();
+                b.next();
+            }
+            (Some(wire_a), Some(wire_b)) if wire_a < wire_b => parity.extend(a.next()),
+            (Some(_), Some(_)) => parity.extend(b.next()),
+            (Some(_), None) => parity.extend(a.next()),
+            (None, Some(_)) => parity.extend(b.next()),
+            (None, None) => return parity,
+        }
+    }
+}
+
+/// Folds an AND of two wire values if it does not depend on a wire, or just passes one wire through
+fn fold_and(a: WireValue, b: WireValue) -> Option<WireValue> {
+    match (a, b) {
+        (WireValue::Constant(false), _) | (_, WireValue::Constant(false)) => Some(WireValue::Constant(false)),
+        (WireValue::Constant(true), other) | (other, WireValue::Constant(true)) => Some(other),
+        (WireValue::Wire(a), WireValue::Wire(b)) if a == b => Some(WireValue::Wire(a)),
+        _ => None,
+    }
+}
+
+/// Returns the wire carrying the value of the given wire, which must not be constant
+fn resolve(values: &BTreeMap<WireId, WireValue>, wire: WireId) -> WireId {
+    match values.get(&wire) {
+        Some(WireValue::Wire(source)) => *source,
+        _ => wire,
+    }
+}
+
+/// Builder for constructing circuits programmatically instead of parsing bristol text.
+/// Input ports need to be declared before any gates are added, as they occupy the lowest wires.
+#[derive(Debug, Default)]
+pub struct CircuitBuilder {
+    num_input_wires: Vec<u32>,
+    num_output_wires: Vec<u32>,
+    input_types: Vec<PortType>,
+    output_types: Vec<PortType>,
+    gates: Vec<Gate>,
+
+    // lowest wire that is neither an input wire nor referenced by any gate
+    next_wire: u32,
+}
+
+impl CircuitBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares a new input port with the given number of wires and returns these wires
+    pub fn input_port(&mut self, width: u32) -> Vec<WireId> {
+        let start: u32 = self.num_input_wires.iter().sum();
+        self.num_input_wires.push(width);
+        self.input_types.push(PortType::Bits);
+        self.next_wire = self.next_wire.max(start + width);
+        (start..start + width).map(WireId).collect()
+    }
+
+    /// Declares a new output port with the given number of wires.
+    /// Output ports occupy the highest wires of the circuit, in the order they are declared.
+    pub fn output_port(&mut self, width: u32) -> &mut Self {
+        self.num_output_wires.push(width);
+        self.output_types.push(PortType::Bits);
+        self
+    }
+
+    /// Declares a new output port consisting of copies of the given wires.
+    /// The copies are placed on newly allocated wires using EQW gates, so this needs to be called
+    /// after all other gates and wires have been added.
+    pub fn output_port_from(&mut self, wires: &[WireId]) -> &mut Self {
+        self.num_output_wires.push(wires.len() as u32);
+        self.output_types.push(PortType::Bits);
+        for &wire in wires {
+            let output = self.new_wire();
+            self.add_eqw(wire, output);
+        }
+        self
+    }
+
+    /// Sets the type of an already declared input port, which is plain bits by default
+    pub fn set_input_type(&mut self, port: usize, port_type: PortType) -> &mut Self {
+        self.input_types[port] = port_type;
+        self
+    }
+
+    /// Sets the type of an already declared output port, which is plain bits by default
+    pub fn set_output_type(&mut self, port: usize, port_type: PortType) -> &mut Self {
+        self.output_types[port] = port_type;
+        self
+    }
+
+    /// Allocates a new wire that is not used by any port or gate yet
+    pub fn new_wire(&mut self) -> WireId {
+        self.next_wire += 1;
+        WireId(self.next_wire - 1)
+    }
+
+    /// Adds a XOR gate computing `out = a ^ b`
+    pub fn add_xor(&mut self, a: WireId, b: WireId, out: WireId) -> &mut Self {
+        self.add_gate(Gate::XOR { input_a: a, input_b: b, output: out })
+    }
+
+    /// Adds an AND gate computing `out = a & b`
+    pub fn add_and(&mut self, a: WireId, b: WireId, out: WireId) -> &mut Self {
+        self.add_gate(Gate::AND { input_a: a, input_b: b, output: out })
+    }
+
+    /// Adds an INV gate computing `out = !inp`
+    pub fn add_inv(&mut self, inp: WireId, out: WireId) -> &mut Self {
+        self.add_gate(Gate::INV { input: inp, output: out })
+    }
+
+    /// Adds an EQW gate computing `out = inp`
+    pub fn add_eqw(&mut self, inp: WireId, out: WireId) -> &mut Self {
+        self.add_gate(Gate::EQW { input: inp, output: out })
+    }
+
+    /// Adds an EQ gate assigning the constant to `out`
+    pub fn add_eq(&mut self, constant: bool, out: WireId) -> &mut Self {
+        self.add_gate(Gate::EQ { constant, output: out })
+    }
+
+    /// Adds gates computing `out = a | b` as `(a ^ b) ^ (a & b)`.
+    /// This costs 3 gates (2 XOR and 1 AND) and 2 newly allocated intermediate wires.
+    pub fn add_or(&mut self, a: WireId, b: WireId, out: WireId) -> &mut Self {
+        let a_xor_b = self.new_wire();
+        let a_and_b = self.new_wire();
+        self.add_xor(a, b, a_xor_b)
+            .add_and(a, b, a_and_b)
+            .add_xor(a_xor_b, a_and_b, out)
+    }
+
+    /// Adds gates computing `out = !(a & b)`.
+    /// This costs 2 gates (1 AND and 1 INV) and 1 newly allocated intermediate wire.
+    pub fn add_nand(&mut self, a: WireId, b: WireId, out: WireId) -> &mut Self {
+        let a_and_b = self.new_wire();
+        self.add_and(a, b, a_and_b)
+            .add_inv(a_and_b, out)
+    }
+
+    /// Adds gates computing `out = !(a | b)`.
+    /// This costs 4 gates (2 XOR, 1 AND, and 1 INV) and 3 newly allocated intermediate wires.
+    pub fn add_nor(&mut self, a: WireId, b: WireId, out: WireId) -> &mut Self {
+        let a_or_b = self.new_wire();
+        self.add_or(a, b, a_or_b)
+            .add_inv(a_or_b, out)
+    }
+
+    /// Adds a full adder of the bits `a`, `b` and `carry_in` and returns the newly allocated
+    /// `(sum, carry_out)` wires. The carry is computed as `((a ^ carry_in) & (b ^ carry_in)) ^ carry_in`,
+    /// s.t. this costs only 1 AND besides 4 XOR gates and 3 intermediate wires.
+    pub fn add_full_adder(&mut self, a: WireId, b: WireId, carry_in: WireId) -> (WireId, WireId) {
+        let a_xor_c = self.new_wire();
+        let b_xor_c = self.new_wire();
+        let sum = self.new_wire();
+        let carry_and = self.new_wire();
+        let carry_out = self.new_wire();
+        self.add_xor(a, carry_in, a_xor_c)
+            .add_xor(b, carry_in, b_xor_c)
+            .add_xor(a_xor_c, b, sum)
+            .add_and(a_xor_c, b_xor_c, carry_and)
+            .add_xor(carry_and, carry_in, carry_out);
+        (sum, carry_out)
+    }
+
+    /// Builds the circuit, deriving the number of gates and wires from the added gates and ports
+    pub fn build(self) -> Result<Circuit, ValidationError> {
+        let num_gates = self.gates.len() as u32;
+        let num_wires = self.next_wire.max(self.num_output_wires.iter().sum());
+        let circuit = Circuit {
+            header: Header {
+                num_gates,
+                num_wires,
+                num_input_wires: self.num_input_wires,
+                num_output_wires: self.num_output_wires,
+                // circuits with only plain bits are equal to parsed circuits, which have no types
+                input_types: Some(self.input_types).filter(|types| types.iter().any(|&port_type| port_type != PortType::Bits)),
+                output_types: Some(self.output_types).filter(|types| types.iter().any(|&port_type| port_type != PortType::Bits)),
+            },
+            gates: self.gates,
+        };
+        circuit.validate()?;
+        Ok(circuit)
+    }
+
+    fn add_gate(&mut self, gate: Gate) -> &mut Self {
+        for wire in gate.inputs().into_iter().chain(gate.outputs()) {
+            self.use_wire(wire);
+        }
+        self.gates.push(gate);
+        self
+    }
+
+    /// Marks the wire as used, s.t. newly allocated wires are placed after it
+    fn use_wire(&mut self, wire: WireId) {
+        self.next_wire = self.next_wire.max(wire.0 + 1);
+    }
+}
+
+/// Returns the content of a line of a bristol file, or None if the line is empty or a comment.
+/// Comments are lines starting with a `#`, and trailing whitespace (including the `\r` of CRLF
+/// line endings) is trimmed from every line.
+fn content_of_line(line: &str) -> Option<&str> {
+    let line = line.trim_end();
+    (!line.is_empty() && !line.trim_start().starts_with('#')).then_some(line)
+}
+
+/// Checks that a line in the position of a header line only consists of numbers, to report stray
+/// lines in front of the header, or gates in place of missing header lines, instead of misreading them.
+/// Signed numbers pass the check, s.t. negative numbers are reported as `InvalidNumber` when parsed.
+fn check_header_line(header_line: &str, line: u32) -> Result<(), ParseError> {
+    let is_number = |token: &str| {
+        let digits = token.strip_prefix(['-', '+']).unwrap_or(token);
+        !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_digit())
+    };
+    match tokenize(header_line).find(|(_, token)| !is_number(token)) {
+        Some((column, token)) => Err(MalformedHeader {
+            line,
+            description: format!("header lines may only contain numbers, but column {column} holds {token:?}"),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Parses the bristol file header, expecting to get the first three lines (with their line numbers) as an argument
+fn parse_header(header_lines: &[(u32, &str)]) -> Result<Header, ParseError> {
+    let (num_gates, num_wires) = parse_header_general(header_lines[0].1, header_lines[0].0)?;
+    let num_input_wires = parse_header_io_wires(header_lines[1].1, header_lines[1].0)?;
+    let num_output_wires = parse_header_io_wires(header_lines[2].1, header_lines[2].0)?;
+    check_port_widths(num_wires, &num_input_wires, header_lines[1].0)?;
+    check_port_widths(num_wires, &num_output_wires, header_lines[2].0)?;
+
+    Ok(Header {
+        num_gates,
+        num_wires,
+        num_input_wires,
+        num_output_wires,
+        input_types: None,
+        output_types: None,
+    })
+}
+
+/// Parses the header of a classic bristol file, expecting to get the first two lines (with their line numbers) as an argument
+fn parse_header_classic(header_lines: &[(u32, &str)]) -> Result<Header, ParseError> {
+    let (num_gates, num_wires) = parse_header_general(header_lines[0].1, header_lines[0].0)?;
+
+    // the second line holds the number of wires of both inputs and the output
+    let line = header_lines[1].0;
+    let io_wires: Vec<Token> = tokenize(header_lines[1].1).collect();
+    if io_wires.len() != 3 {
+        return Err(MalformedHeader { line, description: format!("expected 3 port widths but got {}", io_wires.len()) });
+    }
+
+    let num_input_wires = vec![parse_non_negative_number(io_wires[0], line)?, parse_non_negative_number(io_wires[1], line)?];
+    let num_output_wires = vec![parse_non_negative_number(io_wires[2], line)?];
+    check_port_widths(num_wires, &num_input_wires, line)?;
+    check_port_widths(num_wires, &num_output_wires, line)?;
+
+    Ok(Header {
+        num_gates,
+        num_wires,
+        num_input_wires,
+        num_output_wires,
+        input_types: None,
+        output_types: None,
+    })
+}
+
+/// Ensures that the input or output ports fit into the wires of the circuit, as the input ports
+/// occupy the lowest and the output ports the highest wires
+fn check_port_widths(num_wires: u32, widths: &[u32], line: u32) -> Result<(), ParseError> {
+    let total: u64 = widths.iter().map(|&width| width as u64).sum();
+    if total > num_wires as u64 {
+        return Err(MalformedHeader { line, description: format!("the ports have {total} wires, but the circuit only has {num_wires} wires") });
+    }
+    Ok(())
+}
+
+/// Parses the first line of the bristol file header
+/// returns: (the total number of gates, the total number of wires)
+fn parse_header_general(header_line: &str, line: u32) -> Result<(u32, u32), ParseError> {
+    let header_line: Vec<Token> = tokenize(header_line).collect();
+    if header_line.len() != 2 {
+        return Err(MalformedHeader { line, description: "expected the number of gates and the number of wires".to_string() });
+    }
+
+    Ok((parse_non_negative_number(header_line[0], line)?,
+        parse_non_negative_number(header_line[1], line)?))
+}
+
+/// Parses the second/third line of the bristol file header containing the number of wires per input/output
+fn parse_header_io_wires(header_line: &str, line: u32) -> Result<Vec<u32>, ParseError> {
+    let header_line: Vec<Token> = tokenize(header_line).collect();
+    let num_ports = parse_non_negative_number(get_next_or_error(&mut header_line.iter().copied(), line, "Missing number of ports")?, line)?;
+    let mut num_wires: Vec<u32> = Vec::new();
+
+    if header_line[1..].len() != num_ports as usize {
+        return Err(MalformedHeader {
+            line,
+            description: format!("expected {num_ports} port widths but got {}", header_line[1..].len()),
+        });
+    }
+    for &line_part in &header_line[1..] {
+        num_wires.push(parse_non_negative_number(line_part, line)?);
+    }
+
+    Ok(num_wires)
+}
+
+#[cfg(feature = "std")]
+/// Minimum number of gates of the same layer `evaluate_parallel` hands to a thread
+const MIN_GATES_PER_THREAD: usize = 1024;
+
+/// Maximum number of gates the parser reserves memory for up front, based on the header
+const MAX_RESERVED_GATES: usize = 1 << 20;
+
+/// Parses a line of the bristol file describing one gate, of the form
+/// `<num inputs> <num outputs> <inputs...> <outputs...> <gate type> <annotation...>`.
+/// The gate type is the first non-numeric token after the declared wires, and the rest of the line
+/// after it is returned as the (optional) annotation of the gate.
+fn parse_gate(gate_line: &str, line: u32) -> Result<(Gate, Option<Token<'_>>), ParseError> {
+    let tokens = tokenize(gate_line);
+    let Some((num_entries, gate_type)) = find_gate_type(gate_line, line)? else {
+        return Err(SyntaxError { line, description: "Missing gate type after the wires of the gate".to_string() });
+    };
+
+    // Adding a gate type only requires a constructor validating its arity and a line in this match
+    let constructor = match gate_type {
+        (_, "XOR") => parse_gate_xor,
+        (_, "AND") => parse_gate_and,
+        (_, "INV" | "NOT") => parse_gate_inv,
+        (_, "EQ") => parse_gate_eq,
+        (_, "EQW") => parse_gate_eqw,
+        (_, "MAND") => parse_gate_mand,
+        (column, gate_type) => return Err(UnknownGate { line, column, got: gate_type.to_string() }),
+    };
+    // The entries in front of the gate type are the numbers of input and output wires and the wires themselves
+    let gate = constructor(&GateEntries::read(tokens.clone().take(num_entries), line)?)?;
+
+    let annotation = tokens.clone().nth(num_entries + 1)
+        .map(|(column, _)| (column, gate_line[column as usize - 1..].trim_end()));
+    Ok((gate, annotation))
+}
+
+/// Finds the gate type of a gate line together with its position among the tokens of the line, which
+/// is the first non-numeric token after the wires declared by the first two tokens
+fn find_gate_type(gate_line: &str, line: u32) -> Result<Option<(usize, Token<'_>)>, ParseError> {
+    let mut tokens = tokenize(gate_line);
+    let (Some(num_inputs), Some(num_outputs)) = (tokens.next(), tokens.next()) else {
+        return Ok(None);
+    };
+    let num_wires = parse_non_negative_number(num_inputs, line)? as usize + parse_non_negative_number(num_outputs, line)? as usize;
+    Ok(tokenize(gate_line).enumerate()
+        .skip(num_wires.saturating_add(2))
+        .find(|(_, (_, token))| !token.bytes().all(|byte| byte.is_ascii_digit())))
+}
+
+/// The entries of a gate line in front of its gate type, i.e. the declared numbers of input and
+/// output wires followed by the (still unparsed) wires themselves
+struct GateEntries<'a> {
+    line: u32,
+    num_inputs: usize,
+    num_outputs: usize,
+    num_wires: usize,
+    wires: Take<Tokens<'a>>,
+}
+
+impl<'a> GateEntries<'a> {
+    /// Reads the declared numbers of input and output wires, independent of the gate type.
+    /// The wires are only tokenized again when they are parsed, s.t. no line needs to be buffered.
+    fn read(mut entries: Take<Tokens<'a>>, line: u32) -> Result<Self, ParseError> {
+        // gate lines have the form `<num inputs> <num outputs> <inputs...> <outputs...> <gate type>`
+        let (Some(num_inputs), Some(num_outputs)) = (entries.next(), entries.next()) else {
+            return Err(SyntaxError { line, description: "Missing number of input/output wires".to_string() });
+        };
+        let num_inputs = parse_non_negative_number(num_inputs, line)? as usize;
+        let num_outputs = parse_non_negative_number(num_outputs, line)? as usize;
+        Ok(GateEntries { line, num_inputs, num_outputs, num_wires: entries.clone().count(), wires: entries })
+    }
+
+    /// Ensures that the gate declares the given fixed numbers of input and output wires and lists
+    /// exactly these wires
+    fn expect_arity(&self, gate: &'static str, num_inputs: usize, num_outputs: usize) -> Result<(), ParseError> {
+        if (self.num_inputs, self.num_outputs) != (num_inputs, num_outputs) {
+            return Err(WrongArity {
+                gate,
+                expected: (num_inputs, num_outputs),
+                found: (self.num_inputs, self.num_outputs),
+                line: self.line,
+            });
+        }
+        self.expect_wire_count()
+    }
+
+    /// Ensures that the line lists exactly as many wires as the gate declares
+    fn expect_wire_count(&self) -> Result<(), ParseError> {
+        let num_wires = self.num_inputs + self.num_outputs;
+        if self.num_wires != num_wires {
+            return Err(SyntaxError {
+                line: self.line,
+                description: format!("Expected {num_wires} wire indices but got {}", self.num_wires),
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the tokens of the input wires, which are only complete after checking the wire count
+    fn input_tokens(&self) -> Take<Take<Tokens<'a>>> {
+        self.wires.clone().take(self.num_inputs)
+    }
+
+    /// Returns the tokens of the output wires, which are only complete after checking the wire count
+    fn output_tokens(&self) -> Skip<Take<Tokens<'a>>> {
+        self.wires.clone().skip(self.num_inputs)
+    }
+
+    /// Returns the token of the i-th input wire, which exists after checking the wire count
+    fn input_token(&self, index: usize) -> Token<'a> {
+        self.input_tokens().nth(index).expect("the wire count has been checked")
+    }
+
+    /// Parses the i-th input wire
+    fn input(&self, index: usize) -> Result<WireId, ParseError> {
+        parse_wire_index(self.input_token(index), self.line)
+    }
+
+    /// Parses the i-th output wire
+    fn output(&self, index: usize) -> Result<WireId, ParseError> {
+        parse_wire_index(self.output_tokens().nth(index).expect("the wire count has been checked"), self.line)
+    }
+
+    fn input_wires(&self) -> Result<Vec<WireId>, ParseError> {
+        self.input_tokens().map(|token| parse_wire_index(token, self.line)).collect()
+    }
+
+    fn output_wires(&self) -> Result<Vec<WireId>, ParseError> {
+        self.output_tokens().map(|token| parse_wire_index(token, self.line)).collect()
+    }
+}
+
+/// helper function to parse a XOR gate line
+fn parse_gate_xor(entries: &GateEntries) -> Result<Gate, ParseError> {
+    // ensure that the number of input and output wires in the gate line is correct
+    entries.expect_arity("XOR", 2, 1)?;
+    Ok(Gate::XOR { input_a: entries.input(0)?, input_b: entries.input(1)?, output: entries.output(0)? })
+}
+
+/// helper function to parse a AND gate line
+fn parse_gate_and(entries: &GateEntries) -> Result<Gate, ParseError> {
+    entries.expect_arity("AND", 2, 1)?;
+    Ok(Gate::AND { input_a: entries.input(0)?, input_b: entries.input(1)?, output: entries.output(0)? })
+}
+
+/// helper function to parse a NOT/INV gate line
+fn parse_gate_inv(entries: &GateEntries) -> Result<Gate, ParseError> {
+    entries.expect_arity("INV", 1, 1)?;
+    Ok(Gate::INV { input: entries.input(0)?, output: entries.output(0)? })
+}
+
+/// helper function to parse a EQ gate line
+fn parse_gate_eq(entries: &GateEntries) -> Result<Gate, ParseError> {
+    entries.expect_arity("EQ", 1, 1)?;
+
+    // the input of an EQ gate is not a wire, but the constant assigned to the output
+    let constant = match entries.input_token(0) {
+        (_, "0") => false,
+        (_, "1") => true,
+        (_, constant) => return Err(InvalidEntry { line: entries.line, description: format!("Constant of EQ gate must be 0 or 1 but got {constant:?}") }),
+    };
+    Ok(Gate::EQ { constant, output: entries.output(0)? })
+}
+
+/// helper function to parse a EQW gate line
+fn parse_gate_eqw(entries: &GateEntries) -> Result<Gate, ParseError> {
+    entries.expect_arity("EQW", 1, 1)?;
+    Ok(Gate::EQW { input: entries.input(0)?, output: entries.output(0)? })
+}
+
+/// helper function to parse a MAND gate line
+fn parse_gate_mand(entries: &GateEntries) -> Result<Gate, ParseError> {
+    // the number of wires is not fixed for MAND gates, only their ratio of two inputs per output
+    if entries.num_inputs != 2 * entries.num_outputs {
+        return Err(WrongArity {
+            gate: "MAND",
+            expected: (2 * entries.num_outputs, entries.num_outputs),
+            found: (entries.num_inputs, entries.num_outputs),
+            line: entries.line,
+        });
+    }
+    entries.expect_wire_count()?;
+    Ok(Gate::MAND { inputs: entries.input_wires()?, outputs: entries.output_wires()? })
+}
+
+/// A whitespace separated token of a line together with its 1-based column
+type Token<'a> = (u32, &'a str);
+
+/// splits a line into its whitespace separated tokens, keeping track of the column each token starts at
+fn tokenize(text: &str) -> Tokens<'_> {
+    Tokens { text, tokens: text.split_whitespace() }
+}
+
+/// Iterator over the tokens of a line returned by `tokenize`
+#[derive(Clone)]
+struct Tokens<'a> {
+    text: &'a str,
+    tokens: SplitWhitespace<'a>,
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        self.tokens.next()
+            .map(|token| ((token.as_ptr() as usize - self.text.as_ptr() as usize) as u32 + 1, token))
+    }
+}
+
+/// try to get the next element from an iterator or return a SyntaxError if there is no next element
+fn get_next_or_error<I, T>(iter: &mut I, line: u32, error_msg: &str) -> Result<T, ParseError>
+    where I: Iterator<Item=T> {
+    iter.next().ok_or_else(|| SyntaxError { line, description: error_msg.to_string() })
+}
+fn parse_non_negative_number((column, text): Token, line: u32) -> Result<u32, ParseError> {
+    text.parse().map_err(|_| InvalidNumber { line, column, got: text.to_string() })
+}
+fn parse_wire_index((column, text): Token, line: u32) -> Result<WireId, ParseError> {
+    text.parse().map(WireId).map_err(|_| BadWireIndex { line, column, got: text.to_string() })
+}
+
+// A `#[cfg(test)]` marks the following block as conditionally included only for test builds.
+// cfg directives can achieve similar things as preprocessor directives in C/C++. The tests read
+// files from res/, so they also need the std feature.
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::io::Cursor;
+    use crate::circuit::*;
+    use crate::circuits::SplitMix;
+
+    /// Parses a circuit in the bristol fashion format, which most tests are written in
+    fn parse_fashion(circuit: &str) -> Result<Circuit, ParseError> {
+        Circuit::parse_with_format(circuit, CircuitFormat::Fashion)
+    }
+
+    /// Reads one of the circuits in `res`, which are in the bristol fashion format
+    fn fashion_file(path: &str) -> Result<Circuit, ParseError> {
+        Circuit::from_file_with_format(path, CircuitFormat::Fashion)
+    }
+
+    /// Parses a gate line without its annotation
+    fn parse_gate(gate_line: &str, line: u32) -> Result<Gate, ParseError> {
+        super::parse_gate(gate_line, line).map(|(gate, _)| gate)
+    }
+
+    fn wires(indices: &[u32]) -> Vec<WireId> {
+        indices.iter().copied().map(WireId).collect()
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let input = vec![(1, "42 1337"), (2, "3 10 20 30"), (3, "2 10 20")];
+        let output = parse_header(&input).unwrap();
+
+        assert_eq!(output.num_gates, 42);
+        assert_eq!(output.num_wires, 1337);
+        assert_eq!(output.num_input_wires, vec![10, 20, 30]);
+        assert_eq!(output.num_output_wires, vec![10, 20]);
+    }
+
+    #[test]
+    fn test_parse_gate() {
+        let input_xor = "2 1 42 43 44 XOR";
+        let input_inv = "1 1 16 17 INV";
+        let output_xor = parse_gate(input_xor, 1).unwrap();
+        let output_inv = parse_gate(input_inv, 1).unwrap();
+
+        assert_eq!(output_xor, Gate::XOR { input_a: WireId(42), input_b: WireId(43), output: WireId(44) });
+        assert_eq!(output_inv, Gate::INV { input: WireId(16), output: WireId(17) });
+    }
+
+    #[test]
+    fn test_parse_tiny_circuit() {
+        let input = "4 8\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 4 AND\n\
+            2 1 2 3 5 AND\n\
+            2 1 4 5 6 AND\n\
+            1 1 6 7 INV";
+        let output = parse_fashion(input).unwrap();
+
+        assert_eq!(output.header, Header { num_gates: 4, num_wires: 8, num_input_wires: vec![1, 1, 1, 1], num_output_wires: vec![1], input_types: None, output_types: None });
+        assert_eq!(output.gates[0], Gate::AND { input_a: WireId(0), input_b: WireId(1), output: WireId(4) });
+        assert_eq!(output.gates[1], Gate::AND { input_a: WireId(2), input_b: WireId(3), output: WireId(5) });
+        assert_eq!(output.gates[2], Gate::AND { input_a: WireId(4), input_b: WireId(5), output: WireId(6) });
+        assert_eq!(output.gates[3], Gate::INV { input: WireId(6), output: WireId(7) });
+    }
+
+    #[test]
+    fn test_parse_big_circuit() {
+        let input = read_to_string("res/aes_128.txt").unwrap();
+        let output = parse_fashion(&input).unwrap();
+
+        // Simple plausibility check: Assert that all gates have been parsed
+        assert_eq!(
+            output.gates.iter().filter(
+                |g| matches!(g, Gate::AND {input_a:_, input_b:_, output:_} )
+            ).count(),
+            6400
+        );
+
+        assert_eq!(
+            output.gates.iter().filter(
+                |g| matches!(g, Gate::XOR {input_a:_, input_b:_, output:_} )
+            ).count(),
+            28176
+        );
+
+        assert_eq!(
+            output.gates.iter().filter(
+                |g| matches!(g, Gate::INV {input:_, output:_} )
+            ).count(),
+            2087
+        );
+    }
+
+    #[test]
+    fn test_parse_too_few_lines() {
+        assert!(matches!(parse_fashion(""), Err(TooFewLines { expected: 3, got: 0 })));
+        assert!(matches!(parse_fashion("4 8\n4 1 1 1 1\n"), Err(TooFewLines { expected: 3, got: 2 })));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(parse_fashion("4\n4 1 1 1 1\n1 1"), Err(MalformedHeader { line: 1, .. })));
+        assert!(matches!(parse_fashion("4 8\n4 1 1 1\n1 1"), Err(MalformedHeader { line: 2, .. })));
+        assert!(matches!(parse_gate("2 1 0 1 2 OR", 5), Err(UnknownGate { line: 5, column: 11, got }) if got == "OR"));
+        assert!(matches!(parse_gate("2 1 0 x 2 AND", 5), Err(BadWireIndex { line: 5, column: 7, got }) if got == "x"));
+        assert!(matches!(parse_gate("2 1 0 1 AND", 5), Err(SyntaxError { line: 5, .. })));
+        assert!(matches!(parse_gate("1 1 0 1 XOR", 5), Err(WrongArity { line: 5, .. })));
+    }
+
+    #[test]
+    fn test_parse_error_display() {
+        fn assert_error<E: std::error::Error + Send + Sync + 'static>() {}
+        assert_error::<ParseError>();
+
+        let error = parse_fashion("4\n4 1 1 1 1\n1 1").unwrap_err();
+        assert_eq!(error.to_string(), "Malformed header at line 1: \"expected the number of gates and the number of wires\"");
+        let error = parse_gate("2 1 0 1 2 OR", 5).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown gate type at line 5, column 11: \"OR\"");
+        let error = parse_fashion("").unwrap_err();
+        assert_eq!(error.to_string(), "Too few lines: expected at least 3 non-empty lines but got 0");
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        let input = "1 3\n\
+            2 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 FOO XOR";
+        let error = parse_fashion(input).unwrap_err();
+
+        assert!(matches!(&error, BadWireIndex { line: 5, column: 9, got } if got == "FOO"));
+        assert_eq!(error.to_string(), "Bad wire index at line 5, column 9: expected integer wire index, found \"FOO\"");
+    }
+
+    #[test]
+    fn test_parse_gate_eq() {
+        let output_zero = parse_gate("1 1 0 5 EQ", 1).unwrap();
+        let output_one = parse_gate("1 1 1 6 EQ", 1).unwrap();
+
+        assert_eq!(output_zero, Gate::EQ { constant: false, output: WireId(5) });
+        assert_eq!(output_one, Gate::EQ { constant: true, output: WireId(6) });
+        assert!(matches!(parse_gate("1 1 2 6 EQ", 1), Err(InvalidEntry { line: 1, .. })));
+    }
+
+    #[test]
+    fn test_parse_gate_eqw() {
+        let input_eqw = "1 1 12 30 EQW";
+        let output_eqw = parse_gate(input_eqw, 1).unwrap();
+
+        assert_eq!(output_eqw, Gate::EQW { input: WireId(12), output: WireId(30) });
+        assert!(matches!(parse_gate("2 1 12 13 30 EQW", 1), Err(WrongArity { line: 1, .. })));
+    }
+
+    #[test]
+    fn test_parse_gate_mand() {
+        let input_mand = "4 2 1 2 3 4 10 11 MAND";
+        let output_mand = parse_gate(input_mand, 1).unwrap();
+
+        assert_eq!(output_mand, Gate::MAND { inputs: wires(&[1, 2, 3, 4]), outputs: wires(&[10, 11]) });
+        assert!(matches!(parse_gate("3 2 1 2 3 10 11 MAND", 1), Err(WrongArity { gate: "MAND", expected: (4, 2), found: (3, 2), line: 1 })));
+        assert!(matches!(parse_gate("4 1 1 2 3 4 10 MAND", 1), Err(WrongArity { gate: "MAND", expected: (2, 1), found: (4, 1), line: 1 })));
+        assert!(matches!(parse_gate("4 2 1 2 3 10 11 MAND", 1), Err(SyntaxError { line: 1, .. })));
+    }
+
+    #[test]
+    fn test_evaluate_tiny_circuit() {
+        let input = "4 8\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 4 AND\n\
+            2 1 2 3 5 AND\n\
+            2 1 4 5 6 AND\n\
+            1 1 6 7 INV";
+        let circuit = parse_fashion(input).unwrap();
+
+        // The tiny circuit computes the negated AND of all four inputs
+        assert_eq!(circuit.evaluate(&[vec![true], vec![true], vec![true], vec![true]]), vec![vec![false]]);
+        assert_eq!(circuit.evaluate(&[vec![true], vec![false], vec![true], vec![true]]), vec![vec![true]]);
+        assert_eq!(circuit.evaluate(&[vec![false], vec![false], vec![false], vec![false]]), vec![vec![true]]);
+    }
+
+    #[test]
+    fn test_topo_sort_shuffled_circuit() {
+        let input = "4 8\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            1 1 6 7 INV\n\
+            2 1 4 5 6 AND\n\
+            2 1 2 3 5 AND\n\
+            2 1 0 1 4 AND";
+        let mut circuit = parse_fashion(input).unwrap();
+        circuit.topo_sort().unwrap();
+
+        assert_eq!(circuit.gates, vec![
+            Gate::AND { input_a: WireId(2), input_b: WireId(3), output: WireId(5) },
+            Gate::AND { input_a: WireId(0), input_b: WireId(1), output: WireId(4) },
+            Gate::AND { input_a: WireId(4), input_b: WireId(5), output: WireId(6) },
+            Gate::INV { input: WireId(6), output: WireId(7) },
+        ]);
+        assert_eq!(circuit.evaluate(&[vec![true], vec![true], vec![true], vec![true]]), vec![vec![false]]);
+        assert_eq!(circuit.evaluate(&[vec![true], vec![false], vec![true], vec![true]]), vec![vec![true]]);
+    }
+
+    #[test]
+    fn test_topo_sort_cycle() {
+        let input = "2 4\n\
+            2 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 3 2 AND\n\
+            2 1 1 2 3 XOR";
+        let mut circuit = parse_fashion(input).unwrap();
+
+        assert_eq!(circuit.topo_sort(), Err(CycleError { gate: 0 }));
+    }
+
+    #[test]
+    fn test_find_cycle() {
+        let cyclic = Circuit {
+            header: Header { num_gates: 3, num_wires: 5, num_input_wires: vec![1, 1], num_output_wires: vec![1], input_types: None, output_types: None },
+            gates: vec![
+                Gate::AND { input_a: WireId(0), input_b: WireId(3), output: WireId(2) },
+                Gate::XOR { input_a: WireId(1), input_b: WireId(2), output: WireId(3) },
+                Gate::INV { input: WireId(3), output: WireId(4) },
+            ],
+        };
+        let acyclic = Circuit {
+            header: Header { num_gates: 2, num_wires: 4, num_input_wires: vec![1, 1], num_output_wires: vec![1], input_types: None, output_types: None },
+            gates: vec![
+                Gate::AND { input_a: WireId(0), input_b: WireId(1), output: WireId(2) },
+                Gate::XOR { input_a: WireId(1), input_b: WireId(2), output: WireId(3) },
+            ],
+        };
+
+        assert!(cyclic.has_cycle());
+        assert_eq!(cyclic.find_cycle(), Some(wires(&[2, 3])));
+        assert!(!acyclic.has_cycle());
+        assert_eq!(acyclic.find_cycle(), None);
+    }
+
+    #[test]
+    fn test_to_bristol_round_trip() {
+        let input = "4 8\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 4 AND\n\
+            2 1 2 3 5 AND\n\
+            2 1 4 5 6 AND\n\
+            1 1 6 7 INV";
+        let circuit = parse_fashion(input).unwrap();
+        let bristol = circuit.to_bristol();
+
+        assert_eq!(bristol, format!("{input}\n"));
+        assert_eq!(parse_fashion(&bristol).unwrap(), circuit);
+
+        let all_gates = "6 16\n\
+            2 2 2\n\
+            1 4\n\
+            \n\
+            2 1 0 1 4 XOR\n\
+            2 1 2 3 5 AND\n\
+            1 1 4 6 INV\n\
+            1 1 1 7 EQ\n\
+            1 1 5 8 EQW\n\
+            4 2 4 5 6 7 12 13 MAND\n";
+        assert_eq!(parse_fashion(all_gates).unwrap().to_bristol(), all_gates);
+    }
+
+    #[test]
+    fn test_validate_gate_count() {
+        let input = "4 8\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 4 AND\n\
+            2 1 2 3 5 AND\n\
+            2 1 4 5 6 AND";
+        let truncated = parse_fashion(input).unwrap();
+        let complete = parse_fashion(&format!("{input}\n1 1 6 7 INV")).unwrap();
+
+        assert_eq!(truncated.validate(), Err(ValidationError::GateCountMismatch { declared: 4, actual: 3 }));
+        assert_eq!(complete.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_wire_range() {
+        let input = "4 8\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 4 AND\n\
+            2 1 2 3 5 AND\n\
+            2 1 4 5 6 AND\n\
+            1 1 6 8 INV";
+        let circuit = parse_fashion(input).unwrap();
+
+        assert_eq!(circuit.validate(), Err(ValidationError::WireOutOfRange { gate: 3, wire: WireId(8), num_wires: 8 }));
+    }
+
+    #[test]
+    fn test_parse_comments() {
+        let input = "# tiny test circuit\n\
+            # computes the negated AND of four bits\n\
+            4 8\n\
+            4 1 1 1 1\n\
+            \t# one output bit\n\
+            1 1\n\
+            \n\
+            2 1 0 1 4 AND\n\
+            # second layer\n\
+            2 1 2 3 5 AND\n\
+            2 1 4 5 6 AND\n\
+            \x20  # negate the result\n\
+            1 1 6 7 INV";
+        let output = parse_fashion(input).unwrap();
+
+        assert_eq!(output.header, Header { num_gates: 4, num_wires: 8, num_input_wires: vec![1, 1, 1, 1], num_output_wires: vec![1], input_types: None, output_types: None });
+        assert_eq!(output.gates.len(), 4);
+        assert_eq!(output.gates[3], Gate::INV { input: WireId(6), output: WireId(7) });
+        assert!(matches!(parse_fashion("# only\n4 8\n# a comment"), Err(TooFewLines { expected: 3, got: 1 })));
+    }
+
+    #[test]
+    fn test_parse_crlf() {
+        let lf = "4 8\n4 1 1 1 1\n1 1\n\n2 1 0 1 4 AND\n2 1 2 3 5 AND\n2 1 4 5 6 AND\n1 1 6 7 INV\n";
+        let crlf = "4 8\r\n4 1 1 1 1 \r\n1 1\r\n\r\n2 1 0 1 4 AND\r\n2 1 2 3 5 AND\t\r\n \r\n2 1 4 5 6 AND\r\r\n1 1 6 7 INV\r\n";
+
+        assert_eq!(parse_fashion(crlf).unwrap(), parse_fashion(lf).unwrap());
+    }
+
+    #[test]
+    fn test_getters() {
+        let input = "4 8\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 4 AND\n\
+            2 1 2 3 5 AND\n\
+            2 1 4 5 6 AND\n\
+            1 1 6 7 INV";
+        let circuit = parse_fashion(input).unwrap();
+
+        assert_eq!(circuit.header().num_gates(), 4);
+        assert_eq!(circuit.header().num_wires(), 8);
+        assert_eq!(circuit.header().input_wires(), &[1, 1, 1, 1]);
+        assert_eq!(circuit.header().output_wires(), &[1]);
+        assert_eq!(circuit.gates().len(), 4);
+        assert_eq!(circuit.gates()[0], Gate::AND { input_a: WireId(0), input_b: WireId(1), output: WireId(4) });
+    }
+
+    #[test]
+    fn test_display_gate() {
+        assert_eq!(Gate::XOR { input_a: WireId(42), input_b: WireId(43), output: WireId(44) }.to_string(), "2 1 42 43 44 XOR");
+        assert_eq!(Gate::AND { input_a: WireId(0), input_b: WireId(1), output: WireId(4) }.to_string(), "2 1 0 1 4 AND");
+        assert_eq!(Gate::INV { input: WireId(16), output: WireId(17) }.to_string(), "1 1 16 17 INV");
+        assert_eq!(Gate::EQ { constant: true, output: WireId(5) }.to_string(), "1 1 1 5 EQ");
+        assert_eq!(Gate::EQW { input: WireId(12), output: WireId(30) }.to_string(), "1 1 12 30 EQW");
+        assert_eq!(Gate::MAND { inputs: wires(&[1, 2, 3, 4]), outputs: wires(&[10, 11]) }.to_string(), "4 2 1 2 3 4 10 11 MAND");
+    }
+
+    #[test]
+    fn test_gate_counts() {
+        let input = "4 8\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 4 AND\n\
+            2 1 2 3 5 AND\n\
+            2 1 4 5 6 AND\n\
+            1 1 6 7 INV";
+        let circuit = parse_fashion(input).unwrap();
+
+        assert_eq!(circuit.and_count(), 3);
+        assert_eq!(circuit.gate_counts(), GateCounts { xor: 0, and: 3, inv: 1, mand: 0 });
+    }
+
+    #[test]
+    fn test_and_depth() {
+        let input = "4 8\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 4 AND\n\
+            2 1 2 3 5 AND\n\
+            2 1 4 5 6 AND\n\
+            1 1 6 7 INV";
+        assert_eq!(parse_fashion(input).unwrap().and_depth(), 2);
+
+        // out = ((a & b) ^ c) & (a & (b ^ c)), the XOR gates do not increase the depth
+        let input = "5 8\n\
+            3 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 1 2 3 XOR\n\
+            2 1 0 1 4 AND\n\
+            2 1 4 2 5 XOR\n\
+            2 1 0 3 6 AND\n\
+            2 1 5 6 7 AND";
+        assert_eq!(parse_fashion(input).unwrap().and_depth(), 2);
+
+        let chain = parse_fashion("3 5\n2 1 1\n1 1\n2 1 0 1 2 AND\n2 1 2 1 3 AND\n2 1 3 0 4 AND").unwrap();
+        assert_eq!(chain.and_depth(), 3);
+    }
+
+    #[test]
+    fn test_unused_wires() {
+        // wire 5 is computed but never used, and input wire 2 is never read
+        let input = "3 7\n\
+            3 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 4 AND\n\
+            2 1 0 1 5 XOR\n\
+            1 1 4 6 INV";
+        let circuit = parse_fashion(input).unwrap();
+
+        assert_eq!(circuit.unused_wires(), wires(&[2, 5]));
+    }
+
+    #[test]
+    fn test_builder_tiny_circuit() {
+        let input = "4 8\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 4 AND\n\
+            2 1 2 3 5 AND\n\
+            2 1 4 5 6 AND\n\
+            1 1 6 7 INV";
+
+        let mut builder = CircuitBuilder::new();
+        let inputs: Vec<Vec<WireId>> = (0..4).map(|_| builder.input_port(1)).collect();
+        assert_eq!(inputs, vec![wires(&[0]), wires(&[1]), wires(&[2]), wires(&[3])]);
+        builder.output_port(1)
+            .add_and(WireId(0), WireId(1), WireId(4))
+            .add_and(WireId(2), WireId(3), WireId(5))
+            .add_and(WireId(4), WireId(5), WireId(6))
+            .add_inv(WireId(6), WireId(7));
+
+        assert_eq!(builder.build().unwrap(), parse_fashion(input).unwrap());
+    }
+
+    #[test]
+    fn test_from_file() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+
+        assert_eq!(circuit.header, Header { num_gates: 4, num_wires: 8, num_input_wires: vec![1, 1, 1, 1], num_output_wires: vec![1], input_types: None, output_types: None });
+        assert_eq!(circuit.gates[3], Gate::INV { input: WireId(6), output: WireId(7) });
+        assert!(matches!(fashion_file("res/does_not_exist.txt"), Err(Io(_))));
+    }
+
+    #[test]
+    fn test_parse_reader() {
+        let input = read_to_string("res/aes_128.txt").unwrap();
+        let output = Circuit::parse_reader_with_format(Cursor::new(input.as_bytes()), CircuitFormat::Fashion).unwrap();
+
+        assert_eq!(output, parse_fashion(&input).unwrap());
+        assert!(matches!(Circuit::parse_reader_with_format(Cursor::new("4 8\r\n\r\n4 1 1 1 1"), CircuitFormat::Fashion), Err(TooFewLines { expected: 3, got: 2 })));
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        let dot = circuit.to_dot();
+
+        assert!(dot.starts_with("digraph circuit {\n"));
+        assert_eq!(dot.matches(" -> ").count(), 8);
+        assert!(dot.contains("    in0 -> g0 [label=\"0\"];"));
+        assert!(dot.contains("    g2 [label=\"AND\"];"));
+        assert!(dot.contains("    g3 -> out0 [label=\"7\"];"));
+    }
+
+    #[test]
+    fn test_builder_or() {
+        let mut builder = CircuitBuilder::new();
+        let a = builder.input_port(1)[0];
+        let b = builder.input_port(1)[0];
+        let out = builder.new_wire();
+        builder.add_or(a, b, out);
+        builder.output_port_from(&[out]);
+        let circuit = builder.build().unwrap();
+
+        assert!(circuit.gates().iter().all(|gate| matches!(gate, Gate::XOR { .. } | Gate::AND { .. } | Gate::EQW { .. })));
+        for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+            assert_eq!(circuit.evaluate(&[vec![a], vec![b]]), vec![vec![a | b]]);
+        }
+    }
+
+    #[test]
+    fn test_builder_nand_nor() {
+        let mut builder = CircuitBuilder::new();
+        let a = builder.input_port(1)[0];
+        let b = builder.input_port(1)[0];
+        let (nand, nor) = (builder.new_wire(), builder.new_wire());
+        builder.add_nand(a, b, nand)
+            .add_nor(a, b, nor)
+            .output_port_from(&[nand])
+            .output_port_from(&[nor]);
+        let circuit = builder.build().unwrap();
+
+        for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+            assert_eq!(circuit.evaluate(&[vec![a], vec![b]]), vec![vec![!(a & b)], vec![!(a | b)]]);
+        }
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        // out_0 = (a & 1) ^ (b & 0) ^ !0, out_1 = EQW(c ^ 1) & a, out_2 = (a ^ a) | b
+        let input = "12 16\n\
+            3 1 1 1\n\
+            3 1 1 1\n\
+            \n\
+            1 1 0 3 EQ\n\
+            1 1 1 4 EQ\n\
+            2 1 0 4 5 AND\n\
+            2 1 1 3 6 AND\n\
+            2 1 5 6 7 XOR\n\
+            1 1 3 8 INV\n\
+            2 1 2 4 9 XOR\n\
+            1 1 9 10 EQW\n\
+            2 1 0 0 11 XOR\n\
+            2 1 7 8 13 XOR\n\
+            2 1 10 0 14 AND\n\
+            2 1 11 1 15 XOR";
+        let original = parse_fashion(input).unwrap();
+        let mut folded = parse_fashion(input).unwrap();
+        folded.fold_constants();
+
+        assert_eq!(folded.gates, vec![
+            Gate::INV { input: WireId(2), output: WireId(9) },
+            Gate::INV { input: WireId(0), output: WireId(13) },
+            Gate::AND { input_a: WireId(9), input_b: WireId(0), output: WireId(14) },
+            Gate::EQW { input: WireId(1), output: WireId(15) },
+        ]);
+        assert_eq!(folded.header.num_gates, 4);
+        for bits in 0..8 {
+            let inputs: Vec<Vec<bool>> = (0..3).map(|i| vec![bits >> i & 1 == 1]).collect();
+            assert_eq!(folded.evaluate(&inputs), original.evaluate(&inputs));
+        }
+    }
+
+    #[test]
+    fn test_eliminate_dead_gates() {
+        // wires 4 to 6 form a dead branch that does not reach the output wire 8
+        let input = "5 9\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 4 XOR\n\
+            2 1 4 2 5 AND\n\
+            1 1 5 6 INV\n\
+            2 1 2 3 7 AND\n\
+            1 1 7 8 INV";
+        let original = parse_fashion(input).unwrap();
+        let mut circuit = parse_fashion(input).unwrap();
+        circuit.eliminate_dead_gates();
+
+        assert_eq!(circuit.gates, vec![
+            Gate::AND { input_a: WireId(2), input_b: WireId(3), output: WireId(7) },
+            Gate::INV { input: WireId(7), output: WireId(8) },
+        ]);
+        assert_eq!(circuit.header.num_gates, 2);
+        for bits in 0..16 {
+            let inputs: Vec<Vec<bool>> = (0..4).map(|i| vec![bits >> i & 1 == 1]).collect();
+            assert_eq!(circuit.evaluate(&inputs), original.evaluate(&inputs));
+        }
+    }
+
+    #[test]
+    fn test_deduplicate_gates() {
+        // wire 5 duplicates wire 4, so the last AND gate computes (a & b) & (a & b)
+        let input = "5 9\n\
+            3 1 1 1\n\
+            2 1 1\n\
+            \n\
+            2 1 0 1 4 AND\n\
+            2 1 1 0 5 AND\n\
+            2 1 4 2 6 XOR\n\
+            2 1 5 2 7 XOR\n\
+            2 1 6 7 8 AND";
+        let original = parse_fashion(input).unwrap();
+        let mut circuit = parse_fashion(input).unwrap();
+        circuit.deduplicate_gates();
+
+        assert_eq!(circuit.gates, vec![
+            Gate::AND { input_a: WireId(0), input_b: WireId(1), output: WireId(4) },
+            Gate::XOR { input_a: WireId(4), input_b: WireId(2), output: WireId(6) },
+            Gate::EQW { input: WireId(6), output: WireId(7) },
+            Gate::AND { input_a: WireId(6), input_b: WireId(6), output: WireId(8) },
+        ]);
+        assert_eq!(circuit.and_count(), 2);
+        for bits in 0..8 {
+            let inputs: Vec<Vec<bool>> = (0..3).map(|i| vec![bits >> i & 1 == 1]).collect();
+            assert_eq!(circuit.evaluate(&inputs), original.evaluate(&inputs));
+        }
+    }
+
+    #[test]
+    fn test_evaluate_shared() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        // precomputed triples (a, b, a & b) for the three AND gates
+        let triples = [(true, false, false), (true, true, true), (false, true, false)];
+
+        for bits in 0..16 {
+            let inputs: Vec<bool> = (0..4).map(|i| bits >> i & 1 == 1).collect();
+            // party B holds a fixed pattern of random-looking shares, party A the rest of the inputs
+            let shares_b = vec![true, false, true, true];
+            let shares_a: Vec<bool> = inputs.iter().zip(&shares_b).map(|(input, share)| input ^ share).collect();
+
+            let (output_a, output_b) = circuit.evaluate_shared(&shares_a, &shares_b, &mut triples.into_iter());
+            let output: Vec<bool> = output_a.iter().zip(&output_b).map(|(a, b)| a ^ b).collect();
+            let expected = circuit.evaluate(&inputs.iter().map(|&bit| vec![bit]).collect::<Vec<_>>());
+
+            assert_eq!(vec![output], expected);
+        }
+    }
+
+    #[test]
+    fn test_triples_required() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        assert_eq!(circuit.triples_required(), 3);
+
+        let circuit = parse_fashion("2 9\n2 2 2\n1 3\n2 1 0 1 4 AND\n6 3 0 1 2 3 4 1 6 7 8 MAND").unwrap();
+        assert_eq!(circuit.triples_required(), 4);
+    }
+
+    #[test]
+    fn test_parse_with_format() {
+        let input = read_to_string("res/aes_128.txt").unwrap();
+        let fashion = Circuit::parse_with_format(&input, CircuitFormat::Fashion).unwrap();
+
+        assert_eq!(fashion.header, Header { num_gates: 36663, num_wires: 36919, num_input_wires: vec![128, 128], num_output_wires: vec![128], input_types: None, output_types: None });
+        assert_eq!(fashion, parse_fashion(&input).unwrap());
+
+        // the same circuit with a classic header
+        let classic_input = input.replacen("2 128 128 \n1 128 \n", "128 128 128\n", 1);
+        let classic = Circuit::parse_with_format(&classic_input, CircuitFormat::Classic).unwrap();
+        assert_eq!(classic, fashion);
+
+        assert!(matches!(
+            Circuit::parse_with_format("1 6\n2 2 2\n4 2 0 1 2 3 4 5 MAND", CircuitFormat::Classic),
+            Err(InvalidEntry { line: 3, .. })
+        ));
+        assert!(matches!(Circuit::parse_with_format("1 6\n2 2\n", CircuitFormat::Classic), Err(MalformedHeader { line: 2, .. })));
+    }
+
+    #[test]
+    fn test_parse_gate_annotation() {
+        assert_eq!(parse_gate("2 1 0 1 4 AND annotation", 1).unwrap(), Gate::AND { input_a: WireId(0), input_b: WireId(1), output: WireId(4) });
+        assert_eq!(parse_gate("1 1 6 7 INV negate result 42", 1).unwrap(), Gate::INV { input: WireId(6), output: WireId(7) });
+        assert!(matches!(parse_gate("2 1 0 1 4 OR annotation", 1), Err(UnknownGate { line: 1, column: 11, got }) if got == "OR"));
+        assert!(matches!(parse_gate("2 1 0 1 4", 1), Err(SyntaxError { line: 1, .. })));
+        // the gate type directly follows the wires, even if a known gate type comes later
+        assert!(matches!(parse_gate("2 1 0 1 4 maj AND", 1), Err(UnknownGate { line: 1, column: 11, got }) if got == "maj"));
+        assert_eq!(super::parse_gate("2 1 0 1 4 AND  from  adder ", 1).unwrap().1, Some((16, "from  adder")));
+
+        let input = "2 5\n2 1 1\n1 1\n2 1 0 1 3 AND carry\n1 1 3 4 INV";
+        let (circuit, annotations) = Circuit::parse_annotated(input, ParseOptions { format: CircuitFormat::Fashion, ..ParseOptions::default() }).unwrap();
+        assert_eq!(circuit, parse_fashion(input).unwrap());
+        assert_eq!(annotations, BTreeMap::from([(0, "carry".to_string())]));
+    }
+
+    #[test]
+    fn test_gates_topological() {
+        let input = "4 8\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            1 1 6 7 INV\n\
+            2 1 4 5 6 AND\n\
+            2 1 2 3 5 AND\n\
+            2 1 0 1 4 AND";
+        let circuit = parse_fashion(input).unwrap();
+        let order: Vec<&Gate> = circuit.gates_topological().unwrap().collect();
+
+        // every gate must come after the gates producing its inputs
+        let mut produced: BTreeSet<WireId> = (0..4).map(WireId).collect();
+        for gate in &order {
+            assert!(gate.inputs().iter().all(|wire| produced.contains(wire)));
+            produced.extend(gate.outputs());
+        }
+        assert_eq!(order.len(), 4);
+        assert_eq!(circuit.gates[0], Gate::INV { input: WireId(6), output: WireId(7) });
+
+        let cyclic = parse_fashion("2 4\n2 1 1\n1 1\n2 1 0 3 2 AND\n2 1 1 2 3 XOR").unwrap();
+        assert!(matches!(cyclic.gates_topological(), Err(CycleError { gate: 0 })));
+    }
+
+    #[test]
+    fn test_fan_out() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        let fan_out = circuit.fan_out(false);
+
+        assert_eq!(fan_out[&WireId(4)], 1);
+        assert_eq!(fan_out[&WireId(5)], 1);
+        assert_eq!(fan_out.len(), 7);
+        assert!(!fan_out.contains_key(&WireId(7)));
+        assert_eq!(circuit.fan_out(true)[&WireId(7)], 1);
+
+        let circuit = parse_fashion("2 4\n1 2\n1 1\n2 1 0 1 2 XOR\n2 1 0 2 3 AND").unwrap();
+        assert_eq!(circuit.fan_out(false), BTreeMap::from([(WireId(0), 2), (WireId(1), 1), (WireId(2), 1)]));
+    }
+
+    #[test]
+    fn test_wire_liveness() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        let liveness = circuit.wire_liveness();
+
+        let expected: Vec<(WireId, usize)> = wires(&[0, 1, 2, 3, 4, 5, 6]).into_iter().zip([0, 0, 1, 1, 2, 2, 3]).collect();
+        assert_eq!(liveness, expected);
+
+        let circuit = parse_fashion("2 4\n1 2\n1 1\n2 1 0 1 2 XOR\n2 1 0 2 3 AND").unwrap();
+        assert_eq!(circuit.wire_liveness(), vec![(WireId(0), 1), (WireId(1), 0), (WireId(2), 1)]);
+    }
+
+    #[test]
+    fn test_evaluate_compact() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        for bits in 0..16 {
+            let inputs: Vec<Vec<bool>> = (0..4).map(|i| vec![bits >> i & 1 == 1]).collect();
+            assert_eq!(circuit.evaluate_compact(&inputs), circuit.evaluate(&inputs));
+        }
+
+        // medium sized circuit, whose gates read pseudo-random earlier wires
+        let circuit = crate::circuits::random(16, 500, 38);
+        let mut rng = SplitMix(38);
+        for _ in 0..16 {
+            let inputs = vec![(0..16).map(|_| rng.below(2) == 1).collect::<Vec<bool>>()];
+            assert_eq!(circuit.evaluate_compact(&inputs), circuit.evaluate(&inputs));
+        }
+    }
+
+    #[test]
+    fn test_evaluate_packed() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        // lane 0 holds the inputs 1111, lane 1 the inputs 1011
+        let inputs = vec![vec![0b11], vec![0b01], vec![0b11], vec![0b11]];
+        let outputs = circuit.evaluate_packed(&inputs);
+
+        assert_eq!(outputs[0][0] & 1, 0);
+        assert_eq!(outputs[0][0] >> 1 & 1, 1);
+
+        // the lanes must match the results of evaluating each set of inputs separately
+        for lane in 0..2 {
+            let unpacked: Vec<Vec<bool>> = inputs.iter().map(|input| vec![input[0] >> lane & 1 == 1]).collect();
+            assert_eq!(circuit.evaluate(&unpacked), vec![vec![outputs[0][0] >> lane & 1 == 1]]);
+        }
+    }
+
+    #[test]
+    fn test_check_against() {
+        let circuit = parse_fashion("1 3\n2 1 1\n1 1\n2 1 0 1 2 XOR").unwrap();
+
+        assert_eq!(circuit.check_against(|inputs| vec![inputs[0] ^ inputs[1]], 32), Ok(()));
+
+        let mismatch = circuit.check_against(|inputs| vec![inputs[0] & inputs[1]], 32).unwrap_err();
+        assert_eq!(mismatch.got, vec![mismatch.inputs[0] ^ mismatch.inputs[1]]);
+        assert_eq!(mismatch.expected, vec![mismatch.inputs[0] & mismatch.inputs[1]]);
+    }
+
+    #[test]
+    fn test_validate_duplicate_outputs() {
+        let input = "4 8\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 4 AND\n\
+            2 1 2 3 6 AND\n\
+            2 1 4 1 6 AND\n\
+            1 1 6 7 INV";
+        let circuit = parse_fashion(input).unwrap();
+        assert_eq!(circuit.validate(), Err(ValidationError::DuplicateOutput { wire: WireId(6), first_gate: 1, second_gate: 2 }));
+
+        let circuit = parse_fashion(&input.replace("2 1 4 1 6 AND", "2 1 4 1 2 AND")).unwrap();
+        assert_eq!(circuit.validate(), Err(ValidationError::InputWireAssigned { gate: 2, wire: WireId(2) }));
+    }
+
+    #[test]
+    fn test_validate_undriven_wire() {
+        // wire 5 is read by the third AND, but its gate is missing
+        let input = "3 8\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 4 AND\n\
+            2 1 4 5 6 AND\n\
+            1 1 6 7 INV";
+        let circuit = parse_fashion(input).unwrap();
+
+        assert_eq!(circuit.validate(), Err(ValidationError::UndrivenWire(WireId(5))));
+        assert_eq!(fashion_file("res/tiny.txt").unwrap().validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_clone_and_hash() {
+        let original = fashion_file("res/tiny.txt").unwrap();
+        let mut clone = original.clone();
+        assert_eq!(clone, original);
+
+        clone.fold_constants();
+        clone.gates.push(Gate::EQ { constant: true, output: WireId(3) });
+        assert_ne!(clone, original);
+        assert_eq!(original, fashion_file("res/tiny.txt").unwrap());
+
+        let circuits: std::collections::HashSet<Circuit> = [original.clone(), clone, original].into_iter().collect();
+        assert_eq!(circuits.len(), 2);
+    }
+
+    #[test]
+    fn test_renumber() {
+        let mut circuit = fashion_file("res/tiny.txt").unwrap();
+        circuit.renumber(10).unwrap();
+
+        assert_eq!(circuit.header.num_wires, 18);
+        assert_eq!(circuit.gates[0], Gate::AND { input_a: WireId(10), input_b: WireId(11), output: WireId(14) });
+        assert_eq!(circuit.gates[3], Gate::INV { input: WireId(16), output: WireId(17) });
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        // the tiny circuit with gaps between the intermediate wires
+        let input = "4 14\n\
+            4 1 1 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 5 AND\n\
+            2 1 2 3 8 AND\n\
+            2 1 5 8 11 AND\n\
+            1 1 11 13 INV";
+        let mut circuit = parse_fashion(input).unwrap();
+        let expected: Vec<_> = (0..16)
+            .map(|bits: u32| circuit.evaluate(&(0..4).map(|i| vec![bits >> i & 1 == 1]).collect::<Vec<_>>()))
+            .collect();
+        circuit.canonicalize();
 
-        assert_eq!(
-            output.gates.iter().filter(
-                |g| matches!(g, Gate::XOR {input_a:_, input_b:_, output:_} )
-            ).count(),
-            28176
-        );
+        assert_eq!(circuit, fashion_file("res/tiny.txt").unwrap());
+        for (bits, expected) in (0..16).zip(expected) {
+            assert_eq!(circuit.evaluate(&(0..4).map(|i| vec![bits >> i & 1 == 1]).collect::<Vec<_>>()), expected);
+        }
+    }
+
+    #[test]
+    fn test_compose() {
+        let tiny = fashion_file("res/tiny.txt").unwrap();
+        let inv = parse_fashion("1 2\n1 1\n1 1\n1 1 0 1 INV").unwrap();
+        let composed = tiny.compose(&inv).unwrap();
+
+        assert_eq!(composed.header.num_gates, 5);
+        assert_eq!(composed.header.num_wires, 9);
+        assert_eq!(composed.gates[4], Gate::INV { input: WireId(7), output: WireId(8) });
+        assert_eq!(composed.validate(), Ok(()));
+        for bits in 0..16 {
+            let inputs: Vec<Vec<bool>> = (0..4).map(|i| vec![bits >> i & 1 == 1]).collect();
+            assert_eq!(composed.evaluate(&inputs), vec![vec![bits == 15]]);
+        }
+
+        assert_eq!(tiny.compose(&tiny), Err(ComposeError::WidthMismatch { outputs: 1, inputs: 4 }));
+    }
+
+    #[test]
+    fn test_parallel() {
+        let inv = parse_fashion("1 2\n1 1\n1 1\n1 1 0 1 INV").unwrap();
+        let stacked = inv.parallel(&inv).unwrap();
+
+        assert_eq!(stacked.header.num_wires, 4);
+        assert_eq!(stacked.header.num_input_wires, vec![1, 1]);
+        assert_eq!(stacked.header.num_output_wires, vec![1, 1]);
+        assert_eq!(stacked.gates, vec![Gate::INV { input: WireId(0), output: WireId(2) }, Gate::INV { input: WireId(1), output: WireId(3) }]);
+        for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+            assert_eq!(stacked.evaluate(&[vec![a], vec![b]]), vec![vec![!a], vec![!b]]);
+        }
+
+        let tiny = fashion_file("res/tiny.txt").unwrap();
+        let stacked = tiny.parallel(&inv).unwrap();
+        assert_eq!(stacked.validate(), Ok(()));
+        for bits in 0..32 {
+            let inputs: Vec<Vec<bool>> = (0..5).map(|i| vec![bits >> i & 1 == 1]).collect();
+            assert_eq!(stacked.evaluate(&inputs), vec![vec![bits & 15 != 15], vec![bits & 16 == 0]]);
+        }
+    }
+
+    #[test]
+    fn test_port_types() {
+        let mut builder = CircuitBuilder::new();
+        let a = builder.input_port(4);
+        let b = builder.input_port(4);
+        builder.set_input_type(1, PortType::SignedInt);
+        for (a, b) in a.into_iter().zip(b) {
+            let out = builder.new_wire();
+            builder.add_xor(a, b, out);
+        }
+        builder.output_port_from(&wires(&[8, 9, 10, 11])).set_output_type(0, PortType::UnsignedInt);
+        let circuit = builder.build().unwrap();
+
+        assert_eq!(circuit.header().input_type(0), Some(PortType::Bits));
+        assert_eq!(circuit.header().input_type(1), Some(PortType::SignedInt));
+        assert_eq!(circuit.header().output_type(0), Some(PortType::UnsignedInt));
+        assert_eq!(circuit.header().input_type(2), None);
+        assert_eq!(circuit.header().output_type(1), None);
+
+        let parsed = fashion_file("res/tiny.txt").unwrap();
+        assert!((0..4).all(|port| parsed.header().input_type(port) == Some(PortType::Bits)));
+        assert_eq!(parsed.header().output_type(0), Some(PortType::Bits));
+        assert_eq!(parsed.header().input_type(4), None);
+        assert_eq!(parsed.header().output_type(1), None);
+    }
+
+    #[test]
+    fn test_pack_inputs_unpack_outputs() {
+        // 4-bit ripple carry adder, discarding the final carry
+        let mut builder = CircuitBuilder::new();
+        let a = builder.input_port(4);
+        let b = builder.input_port(4);
+        let mut carry = None;
+        let mut sum = Vec::new();
+        for (a, b) in a.into_iter().zip(b) {
+            let a_xor_b = builder.new_wire();
+            builder.add_xor(a, b, a_xor_b);
+            let Some(carry_in) = carry else {
+                let carry_out = builder.new_wire();
+                builder.add_and(a, b, carry_out);
+                sum.push(a_xor_b);
+                carry = Some(carry_out);
+                continue;
+            };
+            let (bit, a_and_b, propagated, carry_out) = (builder.new_wire(), builder.new_wire(), builder.new_wire(), builder.new_wire());
+            builder.add_xor(a_xor_b, carry_in, bit)
+                .add_and(a, b, a_and_b)
+                .add_and(a_xor_b, carry_in, propagated)
+                .add_xor(a_and_b, propagated, carry_out);
+            sum.push(bit);
+            carry = Some(carry_out);
+        }
+        builder.output_port_from(&sum);
+        let circuit = builder.build().unwrap();
+
+        assert_eq!(circuit.pack_inputs(&[5, 3]), vec![vec![true, false, true, false], vec![true, true, false, false]]);
+        for (a, b) in [(5, 3), (9, 4), (15, 1), (0, 0), (7, 12)] {
+            let outputs = circuit.evaluate(&circuit.pack_inputs(&[a, b]));
+            assert_eq!(circuit.unpack_outputs(&outputs), vec![(a + b) % 16]);
+        }
+
+        // signed outputs are sign-extended
+        let mut builder = CircuitBuilder::new();
+        let input = builder.input_port(4);
+        builder.output_port_from(&input).set_output_type(0, PortType::SignedInt);
+        let circuit = builder.build().unwrap();
+        assert_eq!(circuit.unpack_outputs(&circuit.pack_inputs(&[0b1110])), vec![-2i64 as u64]);
+        assert_eq!(circuit.unpack_outputs(&circuit.pack_inputs(&[0b0110])), vec![6]);
+    }
+
+    #[test]
+    fn test_port_wire_indices() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        assert_eq!(circuit.input_wire_indices(), vec![wires(&[0]), wires(&[1]), wires(&[2]), wires(&[3])]);
+        assert_eq!(circuit.output_wire_indices(), vec![wires(&[7])]);
+
+        let circuit = parse_fashion("1 7\n2 2 1\n1 2\n2 1 0 1 5 AND\n2 1 0 2 6 AND").unwrap();
+        assert_eq!(circuit.input_wire_indices(), vec![wires(&[0, 1]), wires(&[2])]);
+        assert_eq!(circuit.output_wire_indices(), vec![wires(&[5, 6])]);
+    }
+
+    /// Generates a random valid circuit with all gate types
+    fn random_circuit(rng: &mut SplitMix) -> Circuit {
+        let mut builder = CircuitBuilder::new();
+        let mut wires: Vec<WireId> = (0..1 + rng.below(3)).flat_map(|_| builder.input_port(1 + rng.below(4) as u32)).collect();
+        for _ in 0..rng.below(20) {
+            let mut pick = || wires[rng.below(wires.len() as u64) as usize];
+            let (a, b) = (pick(), pick());
+            let out = builder.new_wire();
+            match rng.below(5) {
+                0 => builder.add_xor(a, b, out),
+                1 => builder.add_and(a, b, out),
+                2 => builder.add_inv(a, out),
+                3 => builder.add_eqw(a, out),
+                _ => builder.add_gate(Gate::EQ { constant: rng.below(2) == 1, output: out }),
+            };
+            wires.push(out);
+        }
+        let outputs: Vec<WireId> = (0..1 + rng.below(4)).map(|_| wires[rng.below(wires.len() as u64) as usize]).collect();
+        builder.output_port_from(&outputs);
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_parse_random_input() {
+        // gate lines that crashed the parser before it returned errors
+        for gate_line in ["2 1 0 XOR", "XOR", "1 1 0 x INV", "2 1 0 1 2 OR"] {
+            assert!(parse_fashion(&format!("1 3\n2 1 1\n1 1\n{gate_line}")).is_err());
+        }
+
+        let mut rng = SplitMix(0x5eed);
+        for _ in 0..500 {
+            // valid circuits round-trip through the bristol format
+            let circuit = random_circuit(&mut rng);
+            let bristol = circuit.to_bristol();
+            assert_eq!(parse_fashion(&bristol).unwrap(), circuit, "{bristol}");
+
+            // mutated circuits and random bytes may fail to parse, but must never panic
+            let mut bytes = bristol.into_bytes();
+            for _ in 0..1 + rng.below(4) {
+                let position = rng.below(bytes.len() as u64) as usize;
+                match rng.below(3) {
+                    0 => { bytes.remove(position); }
+                    1 => bytes.insert(position, b" 0123456789\nMANDXORINVEQW#-"[rng.below(27) as usize]),
+                    _ => bytes[position] = rng.below(256) as u8,
+                }
+            }
+            let _ = parse_fashion(&String::from_utf8_lossy(&bytes));
+            let random: Vec<u8> = (0..rng.below(64)).map(|_| rng.below(256) as u8).collect();
+            let _ = parse_fashion(&String::from_utf8_lossy(&random));
+        }
+    }
+
+    #[test]
+    fn test_parse_reserves_gates() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        // the Vec may reserve more than asked for, but not less
+        assert!(circuit.gates.capacity() >= 4);
+
+        // wrong gate counts in the header are no problem for the parser
+        let circuit = parse_fashion("4000000000 8\n4 1 1 1 1\n1 1\n2 1 0 1 4 AND").unwrap();
+        assert_eq!(circuit.gates.len(), 1);
+        assert!(circuit.gates.capacity() < 4000000000);
+        let circuit = parse_fashion("0 8\n4 1 1 1 1\n1 1\n2 1 0 1 4 AND\n2 1 2 3 5 AND").unwrap();
+        assert_eq!(circuit.gates.len(), 2);
+    }
+
+    #[test]
+    fn test_evaluate_parallel() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        for bits in 0..16 {
+            let inputs: Vec<Vec<bool>> = (0..4).map(|i| vec![bits >> i & 1 == 1]).collect();
+            assert_eq!(circuit.evaluate_parallel(&inputs), circuit.evaluate(&inputs));
+        }
+
+        // a wide circuit, s.t. its layers are split across threads
+        let mut rng = SplitMix(42);
+        let mut builder = CircuitBuilder::new();
+        let mut wires = builder.input_port(4096);
+        for _ in 0..4 {
+            wires = (0..wires.len())
+                .map(|_| {
+                    let (a, b, out) = (wires[rng.below(4096) as usize], wires[rng.below(4096) as usize], builder.new_wire());
+                    if rng.below(2) == 0 { builder.add_xor(a, b, out) } else { builder.add_and(a, b, out) };
+                    out
+                })
+                .collect();
+        }
+        builder.output_port_from(&wires);
+        let circuit = builder.build().unwrap();
+        for _ in 0..4 {
+            let inputs = vec![(0..4096).map(|_| rng.below(2) == 1).collect()];
+            assert_eq!(circuit.evaluate_parallel(&inputs), circuit.evaluate(&inputs));
+        }
+    }
+
+    #[test]
+    fn test_layers() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        assert_eq!(circuit.layers(), vec![vec![0, 1], vec![2], vec![3]]);
+
+        // the layers do not depend on the order the gates are stored in
+        let input = "3 6\n\
+            2 1 1\n\
+            1 1\n\
+            \n\
+            2 1 3 4 5 AND\n\
+            1 1 0 3 INV\n\
+            2 1 0 1 4 XOR";
+        assert_eq!(parse_fashion(input).unwrap().layers(), vec![vec![1, 2], vec![0]]);
+    }
+
+    #[test]
+    fn test_depth() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        assert_eq!(circuit.depth(), 3);
+        assert_eq!(circuit.and_depth(), 2);
+
+        let xor_chain = parse_fashion("3 5\n2 1 1\n1 1\n2 1 0 1 2 XOR\n1 1 2 3 INV\n2 1 3 0 4 XOR").unwrap();
+        assert_eq!(xor_chain.depth(), 3);
+        assert_eq!(xor_chain.and_depth(), 0);
+    }
+
+    #[test]
+    fn test_invert_outputs() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        let inverted = circuit.invert_outputs();
+
+        assert_eq!(inverted.header.num_gates, 5);
+        assert_eq!(inverted.header.num_wires, 9);
+        assert_eq!(inverted.gates[4], Gate::INV { input: WireId(7), output: WireId(8) });
+        assert_eq!(inverted.validate(), Ok(()));
+        for bits in 0..16 {
+            let inputs: Vec<Vec<bool>> = (0..4).map(|i| vec![bits >> i & 1 == 1]).collect();
+            assert_eq!(inverted.evaluate(&inputs), vec![vec![!circuit.evaluate(&inputs)[0][0]]]);
+        }
+    }
+
+    #[test]
+    fn test_specialize() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+
+        // with the first input fixed to 1, the circuit is the NAND of the other three inputs
+        let specialized = circuit.specialize(&[(0, vec![true])]).unwrap();
+        assert_eq!(specialized.header.num_input_wires, vec![1, 1, 1]);
+        assert_eq!(specialized.validate(), Ok(()));
+        assert!(specialized.gates.len() <= circuit.gates.len());
+        for bits in 0..8 {
+            let inputs: Vec<Vec<bool>> = (0..3).map(|i| vec![bits >> i & 1 == 1]).collect();
+            let original_inputs: Vec<Vec<bool>> = [vec![true]].into_iter().chain(inputs.iter().cloned()).collect();
+            assert_eq!(specialized.evaluate(&inputs), circuit.evaluate(&original_inputs));
+        }
+
+        // with the third input fixed to 0, the output is always 1
+        let specialized = circuit.specialize(&[(2, vec![false])]).unwrap();
+        assert_eq!(specialized.header.num_input_wires, vec![1, 1, 1]);
+        assert_eq!(specialized.gates, vec![Gate::EQ { constant: true, output: WireId(3) }]);
+
+        assert_eq!(circuit.specialize(&[(4, vec![true])]), Err(SpecializeError::NoSuchPort { port: 4, num_ports: 4 }));
+        assert_eq!(circuit.specialize(&[(1, vec![true, false])]), Err(SpecializeError::WidthMismatch { port: 1, width: 1, bits: 2 }));
+    }
+
+    #[test]
+    fn test_gate_types_used() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        assert_eq!(circuit.gate_types_used(), BTreeSet::from(["AND", "INV"]));
+
+        let circuit = parse_fashion("2 6\n2 2 2\n1 2\n1 1 1 4 EQ\n4 2 0 1 2 3 4 5 MAND").unwrap();
+        assert_eq!(circuit.gate_types_used(), BTreeSet::from(["EQ", "MAND"]));
+    }
+
+    #[test]
+    fn test_to_arithmetic_ops() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        let ops = circuit.to_arithmetic_ops();
+
+        assert_eq!(ops.len(), 4);
+        assert_eq!(ops.iter().filter(|op| matches!(op, ArithOp::Mul { .. })).count(), 3);
+        assert_eq!(ops[3], ArithOp::Not { input: WireId(6), output: WireId(7) });
+
+        let circuit = parse_fashion("3 7\n2 2 2\n1 1\n4 2 0 1 2 3 4 5 MAND\n2 1 4 5 6 XOR\n1 1 0 6 EQ").unwrap();
+        assert_eq!(circuit.to_arithmetic_ops(), vec![
+            ArithOp::Mul { input_a: WireId(0), input_b: WireId(2), output: WireId(4) },
+            ArithOp::Mul { input_a: WireId(1), input_b: WireId(3), output: WireId(5) },
+            ArithOp::Add { input_a: WireId(4), input_b: WireId(5), output: WireId(6) },
+            ArithOp::Const { value: false, output: WireId(6) },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_streaming() {
+        #[derive(Default)]
+        struct CountingVisitor {
+            declared: u32,
+            gates: usize,
+            ands: usize,
+        }
+
+        impl GateVisitor for CountingVisitor {
+            fn header(&mut self, header: &Header) {
+                self.declared = header.num_gates();
+            }
+
+            fn gate(&mut self, gate: &Gate) {
+                self.gates += 1;
+                self.ands += matches!(gate, Gate::AND { .. }) as usize;
+            }
+        }
+
+        let mut visitor = CountingVisitor::default();
+        let file = std::fs::File::open("res/tiny.txt").unwrap();
+        Circuit::parse_streaming_with_format(io::BufReader::new(file), CircuitFormat::Fashion, &mut visitor).unwrap();
+        assert_eq!((visitor.declared, visitor.gates, visitor.ands), (4, 4, 3));
+
+        let mut visitor = CountingVisitor::default();
+        let result = Circuit::parse_streaming_with_format(Cursor::new("4 8\n4 1 1 1 1\n1 1\n2 1 0 1 4 AND\n2 1 2 3 5 OR"), CircuitFormat::Fashion, &mut visitor);
+        assert!(matches!(result, Err(UnknownGate { line: 5, .. })));
+        assert_eq!(visitor.gates, 1);
+    }
+
+    #[test]
+    fn test_write() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        let mut bytes: Vec<u8> = Vec::new();
+        circuit.write(&mut bytes).unwrap();
+
+        assert_eq!(Circuit::parse_reader_with_format(Cursor::new(&bytes), CircuitFormat::Fashion).unwrap(), circuit);
+        assert_eq!(String::from_utf8(bytes).unwrap(), circuit.to_bristol());
+    }
+
+    #[test]
+    fn test_builder_full_adder() {
+        let mut builder = CircuitBuilder::new();
+        let inputs = builder.input_port(3);
+        let (sum, carry_out) = builder.add_full_adder(inputs[0], inputs[1], inputs[2]);
+        builder.output_port_from(&[sum, carry_out]);
+        let circuit = builder.build().unwrap();
+
+        assert_eq!(circuit.and_count(), 1);
+        for bits in 0..8u32 {
+            let inputs: Vec<bool> = (0..3).map(|i| bits >> i & 1 == 1).collect();
+            let total = bits.count_ones();
+            assert_eq!(circuit.evaluate(&[inputs]), vec![vec![total & 1 == 1, total >= 2]]);
+        }
+    }
+
+    #[test]
+    fn test_wire_overflow() {
+        let inv = parse_fashion("1 2\n1 1\n1 1\n1 1 0 1 INV").unwrap();
+        let mut tiny = fashion_file("res/tiny.txt").unwrap();
+        tiny.renumber(u32::MAX - 8).unwrap();
+        assert_eq!(tiny.header.num_wires, u32::MAX);
+
+        let mut tiny = fashion_file("res/tiny.txt").unwrap();
+        assert_eq!(tiny.renumber(u32::MAX - 7), Err(WireOverflow { required: u32::MAX as u64 + 1 }));
+        assert_eq!(tiny, fashion_file("res/tiny.txt").unwrap());
+
+        // a circuit just small enough to compose with the INV circuit, which adds a single wire
+        let mut large = inv.clone();
+        large.header.num_wires = u32::MAX - 1;
+        large.gates[0] = Gate::INV { input: WireId(0), output: WireId(u32::MAX - 2) };
+        assert_eq!(large.compose(&inv).unwrap().header.num_wires, u32::MAX);
+        large.header.num_wires = u32::MAX;
+        large.gates[0] = Gate::INV { input: WireId(0), output: WireId(u32::MAX - 1) };
+        assert_eq!(large.compose(&inv), Err(ComposeError::WireOverflow(WireOverflow { required: u32::MAX as u64 + 1 })));
+        assert_eq!(large.parallel(&inv), Err(WireOverflow { required: u32::MAX as u64 + 2 }));
+    }
+
+    #[test]
+    fn test_parse_malformed_header() {
+        let tiny = read_to_string("res/tiny.txt").unwrap();
+
+        let error = parse_fashion(&format!("generated by circuitgen 1.2\n{tiny}")).unwrap_err();
+        assert!(matches!(error, MalformedHeader { line: 1, .. }));
+        assert_eq!(error.to_string(), "Malformed header at line 1: \"header lines may only contain numbers, but column 1 holds \\\"generated\\\"\"");
+
+        // the header misses the line of the output ports, so the first gate takes its place
+        let error = parse_fashion("4 8\n4 1 1 1 1\n2 1 0 1 4 AND\n2 1 2 3 5 AND").unwrap_err();
+        assert!(matches!(error, MalformedHeader { line: 3, .. }));
+
+        // a stray numeric line shifts the header
+        assert!(matches!(parse_fashion(&format!("1\n{tiny}")), Err(MalformedHeader { line: 1, .. })));
+        assert!(parse_fashion(&format!("# comment\n\n{tiny}")).is_ok());
+    }
+
+    #[test]
+    fn test_parse_strict() {
+        let strict = ParseOptions { format: CircuitFormat::Fashion, strict: true, ..ParseOptions::default() };
+        let tiny = read_to_string("res/tiny.txt").unwrap();
+        assert_eq!(Circuit::parse_with_options(&tiny, strict).unwrap(), parse_fashion(&tiny).unwrap());
+
+        // annotated gates and a wrong gate count are fine for the lenient parser only
+        let annotated = tiny.replace("2 1 0 1 4 AND", "2 1 0 1 4 AND first");
+        assert!(parse_fashion(&annotated).is_ok());
+        assert!(matches!(Circuit::parse_with_options(&annotated, strict), Err(SyntaxError { line: 5, .. })));
+
+        let miscounted = tiny.replace("4 8", "5 8");
+        assert!(parse_fashion(&miscounted).is_ok());
+        assert!(matches!(
+            Circuit::parse_with_options(&miscounted, strict),
+            Err(Invalid(ValidationError::GateCountMismatch { declared: 5, actual: 4 }))
+        ));
+    }
+
+    #[test]
+    fn test_gate_wires() {
+        let gates = [
+            (Gate::XOR { input_a: WireId(0), input_b: WireId(1), output: WireId(2) }, vec![0, 1], vec![2]),
+            (Gate::AND { input_a: WireId(3), input_b: WireId(4), output: WireId(5) }, vec![3, 4], vec![5]),
+            (Gate::INV { input: WireId(6), output: WireId(7) }, vec![6], vec![7]),
+            (Gate::EQ { constant: true, output: WireId(8) }, vec![], vec![8]),
+            (Gate::EQW { input: WireId(9), output: WireId(10) }, vec![9], vec![10]),
+            (Gate::MAND { inputs: wires(&[1, 2, 3, 4]), outputs: wires(&[11, 12]) }, vec![1, 2, 3, 4], vec![11, 12]),
+        ];
+        for (gate, inputs, outputs) in gates {
+            assert_eq!(gate.inputs(), wires(&inputs), "{gate}");
+            assert_eq!(gate.outputs(), wires(&outputs), "{gate}");
+        }
+    }
+
+    #[test]
+    fn test_structural_fingerprint() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        let fingerprint = circuit.structural_fingerprint();
+
+        // reordering the gates and renumbering the internal wires keeps the fingerprint
+        let mut shuffled = circuit.clone();
+        shuffled.gates.reverse();
+        shuffled.gates.swap(0, 1);
+        assert_eq!(shuffled.structural_fingerprint(), fingerprint);
+        let renumbered = parse_fashion("4 9\n4 1 1 1 1\n1 1\n2 1 2 3 6 AND\n2 1 0 1 4 AND\n2 1 4 6 5 AND\n1 1 5 8 INV").unwrap();
+        assert_eq!(renumbered.structural_fingerprint(), fingerprint);
+
+        let mut changed = circuit.clone();
+        changed.gates[1] = Gate::XOR { input_a: WireId(2), input_b: WireId(3), output: WireId(5) };
+        assert_ne!(changed.structural_fingerprint(), fingerprint);
+        let mut changed = circuit.clone();
+        changed.gates[0] = Gate::AND { input_a: WireId(0), input_b: WireId(2), output: WireId(4) };
+        assert_ne!(changed.structural_fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn test_is_linear() {
+        assert!(!fashion_file("res/tiny.txt").unwrap().is_linear());
+        assert!(parse_fashion("3 5\n2 1 1\n1 1\n2 1 0 1 2 XOR\n1 1 2 3 INV\n2 1 3 0 4 XOR").unwrap().is_linear());
+        assert!(!parse_fashion("1 6\n2 2 2\n1 2\n4 2 0 1 2 3 4 5 MAND").unwrap().is_linear());
+    }
+
+    #[test]
+    fn test_split_linear_nonlinear() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        assert_eq!(circuit.split_linear_nonlinear(), (vec![3], vec![0, 1, 2]));
+
+        let circuit = parse_fashion("3 7\n2 2 2\n1 1\n4 2 0 1 2 3 4 5 MAND\n1 1 1 3 EQ\n2 1 4 5 6 XOR").unwrap();
+        assert_eq!(circuit.split_linear_nonlinear(), (vec![1, 2], vec![0]));
+    }
+
+    #[test]
+    fn test_truth_table() {
+        let mut builder = CircuitBuilder::new();
+        let a = builder.input_port(2);
+        let b = builder.input_port(2);
+        let outputs: Vec<WireId> = a.into_iter().zip(b)
+            .map(|(a, b)| {
+                let out = builder.new_wire();
+                builder.add_xor(a, b, out);
+                out
+            })
+            .collect();
+        builder.output_port_from(&outputs);
+        let circuit = builder.build().unwrap();
+
+        let table = circuit.truth_table().unwrap();
+        assert_eq!(table.len(), 16);
+        for (row, (inputs, outputs)) in table.iter().enumerate() {
+            assert_eq!(inputs, &(0..4).map(|wire| row >> wire & 1 == 1).collect::<Vec<_>>());
+            assert_eq!(outputs, &vec![inputs[0] ^ inputs[2], inputs[1] ^ inputs[3]]);
+        }
+
+        let mut builder = CircuitBuilder::new();
+        let input = builder.input_port(21);
+        builder.output_port_from(&input[..1]);
+        assert_eq!(builder.build().unwrap().truth_table(), Err(TooManyInputs { inputs: 21 }));
+    }
+
+    #[test]
+    fn test_computed_wire_count() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        assert_eq!(circuit.computed_wire_count(), circuit.header().num_wires());
+
+        // the header undercounts the wires, as the output is placed on wire 3
+        let undercounting = parse_fashion("1 3\n2 1 1\n1 1\n2 1 0 1 3 AND").unwrap();
+        assert_eq!(undercounting.computed_wire_count(), 4);
+        assert!(undercounting.validate().is_err());
+
+        // the header declares an output wire no gate assigns
+        let overcounting = parse_fashion("1 4\n2 1 1\n1 1\n2 1 0 1 2 AND").unwrap();
+        assert_eq!(overcounting.computed_wire_count(), 3);
+        assert_eq!(overcounting.validate(), Err(ValidationError::WireCountMismatch { declared: 4, computed: 3 }));
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        assert_eq!(circuit.estimate_cost(&CostModel::default()), 3);
+
+        // e.g. a protocol where XOR and INV gates need a little local work
+        let model = CostModel { xor: 1, and: 10, inv: 2, ..CostModel::default() };
+        assert_eq!(circuit.estimate_cost(&model), 32);
+
+        let circuit = parse_fashion("1 6\n2 2 2\n1 2\n4 2 0 1 2 3 4 5 MAND").unwrap();
+        assert_eq!(circuit.estimate_cost(&model), 20);
+    }
+
+    #[test]
+    fn test_parse_generic_gate_entries() {
+        // constructor of a hypothetical 3-input majority gate, which only needs to validate its arity
+        let parse_gate_maj = |entries: &GateEntries| -> Result<(Vec<WireId>, WireId), ParseError> {
+            entries.expect_arity("MAJ", 3, 1)?;
+            Ok((entries.input_wires()?, entries.output(0)?))
+        };
+        let parse = |gate_line: &str| {
+            let num_entries = tokenize(gate_line).position(|(_, token)| token == "MAJ").unwrap();
+            parse_gate_maj(&GateEntries::read(tokenize(gate_line).take(num_entries), 1)?)
+        };
 
+        assert_eq!(parse("3 1 0 1 2 3 MAJ").unwrap(), (wires(&[0, 1, 2]), WireId(3)));
+        assert!(matches!(parse("2 1 0 1 3 MAJ"), Err(WrongArity { gate: "MAJ", line: 1, .. })));
+        assert!(matches!(parse("3 1 0 1 3 MAJ"), Err(SyntaxError { line: 1, .. })));
+        assert!(matches!(parse("3 1 0 1 99999999999 3 MAJ"), Err(BadWireIndex { line: 1, column: 9, .. })));
+    }
+
+    #[test]
+    fn test_to_verilog() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        let verilog = circuit.to_verilog("tiny");
+
+        assert!(verilog.starts_with("module tiny(in0, in1, in2, in3, out0);\n"));
+        assert!(verilog.ends_with("endmodule\n"));
+        assert_eq!(verilog.matches("    input [0:0] in").count(), 4);
+        assert!(verilog.contains("    output [0:0] out0;\n"));
+        assert!(verilog.contains("    wire w4, w5, w6;\n"));
+        assert_eq!(verilog.matches("    assign ").count(), 4);
+        assert!(verilog.contains("    assign w4 = in0[0] & in1[0];\n"));
+        assert!(verilog.contains("    assign out0[0] = ~w6;\n"));
+
+        let circuit = parse_fashion("1 6\n2 2 2\n1 2\n4 2 0 1 2 3 4 5 MAND").unwrap();
+        let verilog = circuit.to_verilog("mand");
+        assert!(verilog.contains("    input [1:0] in1;\n"));
+        assert!(!verilog.contains("wire"));
+        assert!(verilog.contains("    assign out0[1] = in0[1] & in1[1];\n"));
+    }
+
+    #[test]
+    fn test_output_expressions() {
+        let input = "4 7\n3 1 1 1\n2 1 1\n\n2 1 0 1 3 XOR\n2 1 3 2 4 XOR\n2 1 3 2 5 AND\n1 1 4 6 INV";
+        let circuit = parse_fashion(input).unwrap();
+        assert_eq!(circuit.output_expressions().unwrap(), vec!["(in0 ^ in1) & in2", "~(in0 ^ in1 ^ in2)"]);
+
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        assert_eq!(circuit.output_expressions().unwrap(), vec!["~(in0 & in1 & in2 & in3)"]);
+
+        // every layer doubles the length of the expression
+        let mut builder = CircuitBuilder::new();
+        let mut wire = builder.input_port(1)[0];
+        for _ in 0..12 {
+            let out = builder.new_wire();
+            builder.add_xor(wire, wire, out);
+            wire = out;
+        }
+        builder.output_port_from(&[wire]);
+        assert!(matches!(builder.build().unwrap().output_expressions(), Err(ExpressionTooLarge { .. })));
+    }
+
+    #[test]
+    fn test_output_depends_on() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        assert!((0..4).all(|input| circuit.output_depends_on(0, input)));
+
+        // the first output only depends on the first two inputs, the second one only on the last input
+        let circuit = parse_fashion("3 6\n3 1 1 1\n2 1 1\n2 1 0 1 3 AND\n1 1 3 4 EQW\n1 1 2 5 INV").unwrap();
+        let dependencies: Vec<Vec<bool>> = (0..2)
+            .map(|output| (0..3).map(|input| circuit.output_depends_on(output, input)).collect())
+            .collect();
+        assert_eq!(dependencies, vec![vec![true, true, false], vec![false, false, true]]);
+    }
+
+    #[test]
+    fn test_parse_wrong_arity() {
+        let error = parse_gate("3 1 0 1 2 3 XOR", 7).unwrap_err();
+        assert!(matches!(error, WrongArity { gate: "XOR", expected: (2, 1), found: (3, 1), line: 7 }));
         assert_eq!(
-            output.gates.iter().filter(
-                |g| matches!(g, Gate::INV {input:_, output:_} )
-            ).count(),
-            2087
+            error.to_string(),
+            "Wrong arity at line 7: XOR gates have 2 input and 1 output wires, but the line declares 3 and 1"
         );
+
+        assert!(matches!(parse_gate("1 2 0 1 2 NOT", 1), Err(WrongArity { gate: "INV", expected: (1, 1), found: (1, 2), .. })));
+    }
+
+    #[test]
+    fn test_diff() {
+        let input = "5 9\n4 1 1 1 1\n1 1\n\n2 1 0 1 4 XOR\n2 1 4 2 5 AND\n1 1 5 6 INV\n2 1 2 3 7 AND\n1 1 7 8 INV";
+        let original = parse_fashion(input).unwrap();
+        let mut optimized = original.clone();
+        optimized.eliminate_dead_gates();
+
+        let diff = original.diff(&optimized);
+        assert_eq!(diff.removed, original.gates[..3].to_vec());
+        assert!(diff.added.is_empty());
+        assert_eq!(diff.header, Some((original.header.clone(), optimized.header.clone())));
+        assert_eq!(optimized.diff(&original).added, original.gates[..3].to_vec());
+
+        // reordering the gates is no difference
+        let mut reordered = original.clone();
+        reordered.gates.swap(0, 3);
+        assert!(original.diff(&reordered).is_empty());
+    }
+
+    #[test]
+    fn test_linearize_xor_regions() {
+        // the output is the parity in0 ^ in1 ^ in2 ^ in0, i.e. !(in1 ^ in2), spread over a chain of gates
+        let input = "5 9\n4 1 1 1 1\n1 1\n\n2 1 0 1 4 XOR\n2 1 4 2 5 XOR\n1 1 5 6 INV\n2 1 6 0 7 XOR\n1 1 7 8 EQW";
+        let original = parse_fashion(input).unwrap();
+        let mut circuit = original.clone();
+        circuit.linearize_xor_regions();
+
+        assert_eq!(circuit.gates, vec![
+            Gate::XOR { input_a: WireId(1), input_b: WireId(2), output: WireId(4) },
+            Gate::INV { input: WireId(4), output: WireId(5) },
+        ]);
+        assert_eq!(circuit.header.num_wires, 6);
+        circuit.validate().unwrap();
+        assert_eq!(circuit.truth_table(), original.truth_table());
+
+        // linear regions between ANDs and with shared wires preserve the evaluation
+        let mut rng = SplitMix(89);
+        for _ in 0..200 {
+            let original = random_circuit(&mut rng);
+            let mut circuit = original.clone();
+            circuit.linearize_xor_regions();
+            circuit.validate().unwrap();
+            assert_eq!(circuit.truth_table(), original.truth_table(), "{}", original.to_bristol());
+        }
+    }
+
+    #[test]
+    fn test_wire_id() {
+        let mut wire = WireId::from(4);
+        assert_eq!(wire + 3, WireId(7));
+        assert_eq!(wire - 1, WireId(3));
+        assert_eq!(WireId(9) - wire, 5);
+        wire += 2;
+        assert_eq!(wire.index(), 6);
+        assert_eq!(u32::from(wire), 6);
+        assert_eq!(wire.to_string(), "6");
+        assert_eq!(wire.checked_add(1), Some(WireId(7)));
+        assert_eq!(wire.checked_add(u32::MAX), None);
+        assert_eq!(wire.checked_sub(6), Some(WireId(0)));
+        assert_eq!(wire.checked_sub(7), None);
+
+        let xor = Gate::XOR { input_a: WireId(0), input_b: WireId(1), output: WireId(2) };
+        assert_eq!(xor.mapped(|wire| wire + 10), Gate::XOR { input_a: WireId(10), input_b: WireId(11), output: WireId(12) });
+    }
+
+    #[test]
+    fn test_evaluate_traced() {
+        let circuit = fashion_file("res/tiny.txt").unwrap();
+        let inputs = vec![vec![true], vec![true], vec![true], vec![false]];
+        let (outputs, trace) = circuit.evaluate_traced(&inputs);
+
+        assert_eq!(outputs, circuit.evaluate(&inputs));
+        assert_eq!(trace.len(), 8);
+        // wire 4 is the AND of the first two inputs and wire 5 the AND of the last two
+        assert!(trace[&WireId(4)]);
+        assert!(!trace[&WireId(5)]);
+        assert!(!trace[&WireId(6)]);
+        assert_eq!(trace[&WireId(7)], outputs[0][0]);
+    }
+
+    #[test]
+    fn test_parse_header_invalid_number() {
+        let negative = parse_fashion("1 -1\n1 1\n1 1\n1 1 0 1 INV");
+        assert!(matches!(negative, Err(InvalidNumber { line: 1, column: 3, got }) if got == "-1"));
+        let overflowing = parse_fashion("1 2\n1 4294967296\n1 1\n1 1 0 1 INV");
+        assert!(matches!(overflowing, Err(InvalidNumber { line: 2, column: 3, got }) if got == "4294967296"));
+    }
+
+    #[test]
+    fn test_port_of_wire() {
+        let tiny = fashion_file("res/tiny.txt").unwrap();
+        assert_eq!(tiny.port_of_wire(WireId(0)), Some((PortKind::Input, 0)));
+        assert_eq!(tiny.port_of_wire(WireId(3)), Some((PortKind::Input, 3)));
+        assert_eq!(tiny.port_of_wire(WireId(5)), Some((PortKind::Internal, 0)));
+        assert_eq!(tiny.port_of_wire(WireId(7)), Some((PortKind::Output, 0)));
+        assert_eq!(tiny.port_of_wire(WireId(8)), None);
+
+        // ports of different widths, including an empty one
+        let circuit = parse_fashion("1 7\n3 2 0 3\n1 2\n2 1 0 4 5 XOR").unwrap();
+        let ports: Vec<_> = (0..7).map(|wire| circuit.port_of_wire(WireId(wire)).unwrap()).collect();
+        assert_eq!(ports, [
+            (PortKind::Input, 0), (PortKind::Input, 0),
+            (PortKind::Input, 2), (PortKind::Input, 2), (PortKind::Input, 2),
+            (PortKind::Output, 0), (PortKind::Output, 0),
+        ]);
+    }
+
+    #[test]
+    fn test_to_smtlib() {
+        let smt = fashion_file("res/tiny.txt").unwrap().to_smtlib();
+        assert!(smt.starts_with("(set-logic QF_UF)\n"));
+        assert_eq!(smt.matches("(declare-const ").count(), 8);
+        assert!(smt.contains("(assert (= w4 (and w0 w1)))\n"));
+        assert!(smt.contains("(assert (= w7 (not w6)))\n"));
+        assert!(smt.ends_with("(define-fun out0_0 () Bool w7)\n"));
+
+        let circuit = parse_fashion("2 4\n2 1 1\n1 1\n2 1 0 1 2 XOR\n1 1 2 3 INV").unwrap();
+        assert!(circuit.to_smtlib().contains("(assert (= w2 (xor w0 w1)))\n"));
+    }
+
+    #[test]
+    fn test_equivalent_to() {
+        let input = "4 7\n\
+            2 2 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 3 AND\n\
+            1 1 2 4 INV\n\
+            2 1 0 2 5 XOR\n\
+            2 1 3 2 6 XOR";
+        let circuit = parse_fashion(input).unwrap();
+        let mut optimized = circuit.clone();
+        optimized.eliminate_dead_gates();
+        assert_eq!(optimized.gates().len(), 2);
+        assert_eq!(circuit.equivalent_to(&optimized), Ok(true));
+
+        assert_eq!(circuit.equivalent_to(&circuit.invert_outputs()), Ok(false));
+        let tiny = fashion_file("res/tiny.txt").unwrap();
+        assert_eq!(circuit.equivalent_to(&tiny), Ok(false));
+
+        let wide = parse_fashion("1 22\n1 21\n1 1\n1 1 0 21 INV").unwrap();
+        assert_eq!(wide.equivalent_to(&wide), Err(TooManyInputs { inputs: 21 }));
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        let tiny = read_to_string("res/tiny.txt").unwrap();
+        assert_eq!(Circuit::parse_bytes_with_format(tiny.as_bytes(), CircuitFormat::Fashion).unwrap(), parse_fashion(&tiny).unwrap());
+
+        let error = Circuit::parse_bytes("1 3\n1 1 1\n1 1 0 2 INV # négation".as_bytes()).unwrap_err();
+        assert!(matches!(error, NonAscii { line: 3, column: 16 }), "{error}");
+        assert!(matches!(Circuit::parse_bytes(b"\xff"), Err(NonAscii { line: 1, column: 1 })));
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let limited = ParseOptions { format: CircuitFormat::Fashion, max_gates: Some(10), max_wires: Some(20), ..ParseOptions::default() };
+        let tiny = read_to_string("res/tiny.txt").unwrap();
+        assert_eq!(Circuit::parse_with_options(&tiny, limited).unwrap(), parse_fashion(&tiny).unwrap());
+
+        let huge_header = "4000000000 8\n4 1 1 1 1\n1 1\n2 1 0 1 4 AND";
+        assert!(matches!(
+            Circuit::parse_with_options(huge_header, limited),
+            Err(LimitExceeded { line: 3, resource: "gates", limit: 10, found: 4000000000 })
+        ));
+        let huge_wires = "1 4000000000\n1 1\n1 1\n1 1 0 3999999999 INV";
+        assert!(matches!(Circuit::parse_with_options(huge_wires, limited), Err(LimitExceeded { resource: "wires", .. })));
+
+        // the limits also apply to the gates if the header understates the size of the circuit
+        let understated = format!("1 8\n4 1 1 1 1\n1 1\n{}", "2 1 0 1 4 AND\n".repeat(11));
+        assert!(matches!(Circuit::parse_with_options(&understated, limited), Err(LimitExceeded { line: 14, resource: "gates", found: 11, .. })));
+        let out_of_range = "1 8\n4 1 1 1 1\n1 1\n2 1 0 1 31 AND";
+        assert!(matches!(Circuit::parse_with_options(out_of_range, limited), Err(LimitExceeded { line: 4, resource: "wires", found: 32, .. })));
+    }
+
+    #[test]
+    fn test_index_gates() {
+        let tiny = fashion_file("res/tiny.txt").unwrap();
+        assert_eq!(tiny[1], Gate::AND { input_a: WireId(2), input_b: WireId(3), output: WireId(5) });
+        assert_eq!(tiny[3], Gate::INV { input: WireId(6), output: WireId(7) });
+        assert!((0..tiny.gates().len()).all(|index| tiny[index] == tiny.gates()[index]));
+    }
+
+    #[test]
+    fn test_into_iter_gates() {
+        let tiny = fashion_file("res/tiny.txt").unwrap();
+        let mut and_count = 0;
+        for gate in &tiny {
+            and_count += matches!(gate, Gate::AND { .. }) as usize;
+        }
+        assert_eq!(and_count, 3);
+        assert_eq!((&tiny).into_iter().count(), 4);
+
+        let expected = tiny.gates().to_vec();
+        let gates: Vec<Gate> = tiny.into_iter().collect();
+        assert_eq!(gates, expected);
+    }
+
+    #[test]
+    fn test_remove_double_inv() {
+        // wire 0 is inverted twice before the AND, while the INV of wire 6 is kept as wire 6 is also
+        // read by the first XOR
+        let input = "7 10\n\
+            2 2 1\n\
+            1 1\n\
+            \n\
+            1 1 0 3 INV\n\
+            1 1 3 4 INV\n\
+            2 1 4 1 5 AND\n\
+            1 1 1 6 INV\n\
+            1 1 6 7 INV\n\
+            2 1 5 6 8 XOR\n\
+            2 1 8 7 9 XOR";
+        let mut circuit = parse_fashion(input).unwrap();
+        let original = circuit.clone();
+        assert_eq!(circuit.remove_double_inv(), 1);
+        assert_eq!(circuit.gates(), [
+            Gate::AND { input_a: WireId(0), input_b: WireId(1), output: WireId(5) },
+            Gate::INV { input: WireId(1), output: WireId(6) },
+            Gate::INV { input: WireId(6), output: WireId(7) },
+            Gate::XOR { input_a: WireId(5), input_b: WireId(6), output: WireId(8) },
+            Gate::XOR { input_a: WireId(8), input_b: WireId(7), output: WireId(9) },
+        ]);
+        assert_eq!(circuit.header.num_gates, 5);
+        assert_eq!(circuit.equivalent_to(&original), Ok(true));
+
+        // a chain of three INVs keeps a single one
+        let mut chain = parse_fashion("3 4\n1 1\n1 1\n1 1 0 1 INV\n1 1 1 2 INV\n1 1 2 3 INV").unwrap();
+        assert_eq!(chain.remove_double_inv(), 1);
+        assert_eq!(chain.gates(), [Gate::INV { input: WireId(0), output: WireId(3) }]);
+
+        // the output wire of the circuit becomes a copy
+        let mut double = parse_fashion("2 3\n1 1\n1 1\n1 1 0 1 INV\n1 1 1 2 INV").unwrap();
+        assert_eq!(double.remove_double_inv(), 1);
+        assert_eq!(double.gates(), [Gate::EQW { input: WireId(0), output: WireId(2) }]);
+
+        let mut rng = SplitMix(100);
+        for _ in 0..200 {
+            let original = random_circuit(&mut rng);
+            let mut circuit = original.clone();
+            circuit.remove_double_inv();
+            circuit.validate().unwrap();
+            assert_eq!(circuit.equivalent_to(&original), Ok(true), "{}", original.to_bristol());
+        }
+    }
+
+    #[test]
+    fn test_parse_ports_exceeding_wires() {
+        assert!(matches!(parse_fashion("1 2\n1 1\n1 5\n1 1 0 1 INV"), Err(MalformedHeader { line: 3, .. })));
+        assert!(matches!(parse_fashion("1 2\n2 4294967295 2\n1 1\n1 1 0 1 INV"), Err(MalformedHeader { line: 2, .. })));
+        let classic = Circuit::parse_with_format("1 3\n2 2 1\n1 1 0 2 INV", CircuitFormat::Classic);
+        assert!(matches!(classic, Err(MalformedHeader { line: 2, .. })));
+        // ports may span all wires of the circuit
+        assert!(parse_fashion("0 2\n1 2\n1 2\n").is_ok());
+    }
+
+    #[test]
+    fn test_parse_defaults_to_classic() {
+        let classic = "2 5\n2 1 1\n2 1 0 1 3 AND\n1 1 3 4 INV";
+        assert_eq!(CircuitFormat::default(), CircuitFormat::Classic);
+        assert_eq!(Circuit::parse(classic).unwrap(), Circuit::parse_with_format(classic, CircuitFormat::Classic).unwrap());
+        assert_eq!(Circuit::parse_reader(Cursor::new(classic)).unwrap(), Circuit::parse(classic).unwrap());
+
+        // fashion files are only parsed when asking for the format
+        let tiny = read_to_string("res/tiny.txt").unwrap();
+        assert!(matches!(Circuit::parse(&tiny), Err(MalformedHeader { line: 2, .. })));
+        assert!(Circuit::from_file("res/tiny.txt").is_err());
+        assert_eq!(fashion_file("res/tiny.txt").unwrap(), parse_fashion(&tiny).unwrap());
+    }
+
+    #[test]
+    fn test_gate_max_wire() {
+        assert_eq!(parse_gate("2 1 7 3 5 XOR", 1).unwrap().max_wire(), Some(WireId(7)));
+        assert_eq!(parse_gate("1 1 2 9 INV", 1).unwrap().max_wire(), Some(WireId(9)));
+        assert_eq!(parse_gate("1 1 1 4 EQ", 1).unwrap().max_wire(), Some(WireId(4)));
+        assert_eq!(parse_gate("4 2 0 8 2 3 4 5 MAND", 1).unwrap().max_wire(), Some(WireId(8)));
+        assert_eq!(parse_gate("0 0 MAND", 1).unwrap().max_wire(), None);
     }
 }
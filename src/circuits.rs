@@ -0,0 +1,212 @@
+//! Generators for commonly used circuits, built with the `CircuitBuilder`
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::circuit::{Circuit, CircuitBuilder, PortType, WireId};
+
+/// Returns a circuit adding two unsigned integers of the given number of bits.
+/// The single output port has one more bit than the inputs, holding the carry-out as the most
+/// significant bit. Panics if the number of bits is 0.
+pub fn ripple_carry_adder(bits: usize) -> Circuit {
+    assert!(bits > 0, "adders need at least one bit");
+    let mut builder = CircuitBuilder::new();
+    let a = builder.input_port(bits as u32);
+    let b = builder.input_port(bits as u32);
+
+    // The least significant bits have no carry-in, so a half adder suffices
+    let (sum, mut carry) = (builder.new_wire(), builder.new_wire());
+    builder.add_xor(a[0], b[0], sum).add_and(a[0], b[0], carry);
+    let mut sums = vec![sum];
+    for (&a, &b) in a.iter().zip(&b).skip(1) {
+        let (sum, carry_out) = builder.add_full_adder(a, b, carry);
+        sums.push(sum);
+        carry = carry_out;
+    }
+    sums.push(carry);
+
+    builder.output_port_from(&sums)
+        .set_input_type(0, PortType::UnsignedInt)
+        .set_input_type(1, PortType::UnsignedInt)
+        .set_output_type(0, PortType::UnsignedInt);
+    builder.build().expect("the adder is a valid circuit")
+}
+
+/// Returns a circuit with a single output bit, which is 1 iff its two inputs of the given number of
+/// bits are equal. The inputs are XORed bitwise, and the differing bits are combined by a tree of
+/// ORs with a NOR at its root. Panics if the number of bits is 0.
+pub fn equality(bits: usize) -> Circuit {
+    assert!(bits > 0, "comparators need at least one bit");
+    let mut builder = CircuitBuilder::new();
+    let a = builder.input_port(bits as u32);
+    let b = builder.input_port(bits as u32);
+
+    let mut differences: Vec<WireId> = a.into_iter().zip(b)
+        .map(|(a, b)| {
+            let difference = builder.new_wire();
+            builder.add_xor(a, b, difference);
+            difference
+        })
+        .collect();
+    // Combine pairs of differences until only the two inputs of the final NOR remain
+    while differences.len() > 2 {
+        differences = differences.chunks(2)
+            .map(|pair| match *pair {
+                [a, b] => {
+                    let a_or_b = builder.new_wire();
+                    builder.add_or(a, b, a_or_b);
+                    a_or_b
+                }
+                [a] => a,
+                _ => unreachable!("chunks have one or two elements"),
+            })
+            .collect();
+    }
+    let equal = builder.new_wire();
+    match differences[..] {
+        [a, b] => builder.add_nor(a, b, equal),
+        [a] => builder.add_inv(a, equal),
+        _ => unreachable!("at least one bit is compared"),
+    };
+
+    builder.output_port_from(&[equal]);
+    builder.build().expect("the comparator is a valid circuit")
+}
+
+/// Returns a circuit selecting one of two inputs of the given number of bits. The input ports are
+/// `sel` (1 bit), `a` and `b`, and the output is `a` if `sel` is 0 and `b` otherwise, computed
+/// bitwise as `a ^ (sel & (a ^ b))`.
+pub fn mux(bits: usize) -> Circuit {
+    let mut builder = CircuitBuilder::new();
+    let sel = builder.input_port(1)[0];
+    let a = builder.input_port(bits as u32);
+    let b = builder.input_port(bits as u32);
+
+    let selected: Vec<WireId> = a.into_iter().zip(b)
+        .map(|(a, b)| {
+            let (a_xor_b, masked, out) = (builder.new_wire(), builder.new_wire(), builder.new_wire());
+            builder.add_xor(a, b, a_xor_b)
+                .add_and(sel, a_xor_b, masked)
+                .add_xor(a, masked, out);
+            out
+        })
+        .collect();
+
+    builder.output_port_from(&selected);
+    builder.build().expect("the multiplexer is a valid circuit")
+}
+
+/// Returns a random, well-formed circuit with the given number of input wires (in a single port)
+/// and gates, which is fully determined by the seed. Each gate has a random type (XOR, AND, INV,
+/// EQW or EQ) and only reads wires assigned before it, so the gates are in topological order.
+/// The outputs of the last one to four gates form the single output port, while circuits without
+/// gates have no output port at all.
+///
+/// The randomness is not suitable for anything but testing.
+pub fn random(num_inputs: usize, num_gates: usize, seed: u64) -> Circuit {
+    let mut rng = SplitMix(seed);
+    let mut builder = CircuitBuilder::new();
+    let mut wires = builder.input_port(num_inputs as u32);
+
+    for _ in 0..num_gates {
+        let pick = |rng: &mut SplitMix| wires[rng.below(wires.len() as u64) as usize];
+        let out = builder.new_wire();
+        // without any assigned wires only constants can be produced
+        match if wires.is_empty() { 4 } else { rng.below(5) } {
+            0 => builder.add_xor(pick(&mut rng), pick(&mut rng), out),
+            1 => builder.add_and(pick(&mut rng), pick(&mut rng), out),
+            2 => builder.add_inv(pick(&mut rng), out),
+            3 => builder.add_eqw(pick(&mut rng), out),
+            _ => builder.add_eq(rng.below(2) == 1, out),
+        };
+        wires.push(out);
+    }
+
+    if num_gates > 0 {
+        builder.output_port(1 + rng.below(num_gates.min(4) as u64) as u32);
+    }
+    builder.build().expect("the random circuit is a valid circuit")
+}
+
+/// The SplitMix64 generator, a small and fast PRNG with a 64 bit state.
+/// It is deterministic, s.t. failures of the randomized tests are reproducible.
+pub(crate) struct SplitMix(pub(crate) u64);
+
+impl SplitMix {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ z >> 30).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ z >> 27).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ z >> 31
+    }
+
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuits::*;
+
+    #[test]
+    fn test_ripple_carry_adder() {
+        let adder = ripple_carry_adder(4);
+        assert_eq!(adder.header().input_wires(), &[4, 4]);
+        assert_eq!(adder.header().output_wires(), &[5]);
+        assert_eq!(adder.and_count(), 4);
+
+        for (a, b) in [(0, 0), (1, 1), (5, 3), (9, 4), (15, 1), (15, 15), (7, 12)] {
+            let outputs = adder.evaluate(&adder.pack_inputs(&[a, b]));
+            assert_eq!(adder.unpack_outputs(&outputs), vec![a + b]);
+        }
+    }
+
+    #[test]
+    fn test_equality() {
+        let comparator = equality(4);
+        assert_eq!(comparator.header().input_wires(), &[4, 4]);
+        assert_eq!(comparator.header().output_wires(), &[1]);
+
+        for (a, b) in [(0, 0), (5, 5), (15, 15), (5, 4), (0, 8), (15, 7), (3, 12)] {
+            let outputs = comparator.evaluate(&comparator.pack_inputs(&[a, b]));
+            assert_eq!(outputs, vec![vec![a == b]], "{a} == {b}");
+        }
+        for bits in [1, 3] {
+            let comparator = equality(bits);
+            for (a, b) in [(0, 0), (1, 1), (1, 0), (0, 1)] {
+                assert_eq!(comparator.evaluate(&comparator.pack_inputs(&[a, b])), vec![vec![a == b]]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mux() {
+        let mux = mux(4);
+        assert_eq!(mux.header().input_wires(), &[1, 4, 4]);
+        assert_eq!(mux.header().output_wires(), &[4]);
+
+        for (a, b) in [(0, 15), (5, 3), (9, 9), (12, 1)] {
+            let outputs = mux.evaluate(&mux.pack_inputs(&[0, a, b]));
+            assert_eq!(mux.unpack_outputs(&outputs), vec![a]);
+            let outputs = mux.evaluate(&mux.pack_inputs(&[1, a, b]));
+            assert_eq!(mux.unpack_outputs(&outputs), vec![b]);
+        }
+    }
+
+    #[test]
+    fn test_random() {
+        for seed in 0..32 {
+            let circuit = random(1 + seed as usize % 8, 50, seed);
+            circuit.validate().unwrap();
+            assert_eq!(circuit.header().num_gates(), 50);
+            assert_eq!(circuit, random(1 + seed as usize % 8, 50, seed));
+
+            let inputs = vec![(0..circuit.header().input_wires()[0]).map(|wire| (seed >> wire) & 1 == 1).collect()];
+            assert_eq!(circuit.evaluate(&inputs)[0].len(), circuit.header().output_wires()[0] as usize);
+        }
+        random(0, 10, 1).validate().unwrap();
+        random(5, 0, 1).validate().unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, Hasher};
+
+use crate::circuit::{Circuit, Gate, WireId};
+
+/// The wire labels of a garbled circuit. Every wire has a random zero-label, while its one-label is
+/// always `zero ^ delta` for the global offset delta. This allows XOR gates to be garbled for free,
+/// as the zero-label of the output is simply the XOR of the zero-labels of the inputs.
+pub struct Labels {
+    delta: u128,
+    // zero-label of each wire, indexed by the wire
+    zero_labels: Vec<u128>,
+}
+
+impl Labels {
+    /// Returns the global offset between the zero- and one-label of every wire
+    pub fn delta(&self) -> u128 {
+        self.delta
+    }
+
+    /// Returns the label encoding a 0 on the wire
+    pub fn zero(&self, wire: WireId) -> u128 {
+        self.zero_labels[wire.index()]
+    }
+
+    /// Returns the label encoding a 1 on the wire
+    pub fn one(&self, wire: WireId) -> u128 {
+        self.zero(wire) ^ self.delta
+    }
+
+    /// Returns the label encoding the given bit on the wire
+    pub fn label(&self, wire: WireId, bit: bool) -> u128 {
+        if bit { self.one(wire) } else { self.zero(wire) }
+    }
+}
+
+/// The garbled table of a single AND, encrypting the output label for each combination of input labels
+pub struct GarbledTable {
+    // wire the AND assigns, which identifies the table
+    pub output: WireId,
+    // the rows are ordered by the select bits (the least significant bits) of the two input labels,
+    // i.e. for input labels `a` and `b` the row `2 * (a & 1) + (b & 1)` holds the encrypted output label
+    pub rows: [u128; 4],
+}
+
+/// A circuit garbled by the garbler. The labels are the secret of the garbler, while the tables
+/// (and the labels of constant wires) are sent to the evaluator.
+pub struct GarbledCircuit {
+    pub labels: Labels,
+    // one table for each AND in the order the gates are evaluated, MAND gates have one table per output
+    pub tables: Vec<GarbledTable>,
+    // the labels of the wires assigned by EQ gates, which encode the constant of the gate
+    pub constant_labels: HashMap<WireId, u128>,
+}
+
+impl Circuit {
+    /// Garbles the circuit using free-XOR and point-and-permute.
+    /// The least significant bit of delta is set to 1 for point-and-permute, s.t. the zero- and
+    /// one-label of each wire always have different select bits.
+    ///
+    /// Note that the random labels are sampled from the randomly keyed hasher of the std library and
+    /// the tables are encrypted with a non-cryptographic hash, so this is not secure for real use.
+    pub fn garble(&self, delta: u128) -> GarbledCircuit {
+        self.garble_with_hash(delta, hash_labels)
+    }
+
+    /// Garbles the circuit like `garble`, but encrypts the rows of the garbled tables with the keys
+    /// derived by the provided hash of the two input labels and the output wire
+    pub fn garble_with_hash(&self, delta: u128, hash: impl Fn(u128, u128, WireId) -> u128) -> GarbledCircuit {
+        let delta = delta | 1;
+        let mut sampler = LabelSampler::new();
+        let mut zero_labels = vec![0; self.header().num_wires() as usize];
+        let mut tables = Vec::new();
+        let mut constant_labels = HashMap::new();
+
+        let num_input_wires: u32 = self.header().input_wires().iter().sum();
+        for wire in 0..num_input_wires {
+            zero_labels[wire as usize] = sampler.sample();
+        }
+
+        for gate in self.gates() {
+            match gate {
+                Gate::XOR { input_a, input_b, output } =>
+                    zero_labels[output.index()] = zero_labels[input_a.index()] ^ zero_labels[input_b.index()],
+                // the one-label of the input encodes a 0 on the output
+                Gate::INV { input, output } =>
+                    zero_labels[output.index()] = zero_labels[input.index()] ^ delta,
+                Gate::EQW { input, output } =>
+                    zero_labels[output.index()] = zero_labels[input.index()],
+                Gate::EQ { constant, output } => {
+                    zero_labels[output.index()] = sampler.sample();
+                    constant_labels.insert(*output, zero_labels[output.index()] ^ if *constant { delta } else { 0 });
+                }
+                Gate::AND { input_a, input_b, output } =>
+                    tables.push(garble_and(&mut zero_labels, delta, &mut sampler, &hash, *input_a, *input_b, *output)),
+                Gate::MAND { inputs, outputs } => {
+                    let (inputs_a, inputs_b) = inputs.split_at(outputs.len());
+                    for ((input_a, input_b), output) in inputs_a.iter().zip(inputs_b).zip(outputs) {
+                        tables.push(garble_and(&mut zero_labels, delta, &mut sampler, &hash, *input_a, *input_b, *output));
+                    }
+                }
+            }
+        }
+
+        GarbledCircuit { labels: Labels { delta, zero_labels }, tables, constant_labels }
+    }
+
+    /// Evaluates the garbled circuit given one label for each input wire, as well as the garbled
+    /// tables and the constant labels produced by the garbler.
+    /// Returns the label of each output wire, which the garbler can map back to the output bits.
+    pub fn evaluate_garbled(&self, tables: &[GarbledTable], constant_labels: &HashMap<WireId, u128>, input_labels: &[u128]) -> Vec<u128> {
+        self.evaluate_garbled_with_hash(tables, constant_labels, input_labels, hash_labels)
+    }
+
+    /// Evaluates the garbled circuit like `evaluate_garbled`, using the hash the circuit was garbled with
+    pub fn evaluate_garbled_with_hash(
+        &self,
+        tables: &[GarbledTable],
+        constant_labels: &HashMap<WireId, u128>,
+        input_labels: &[u128],
+        hash: impl Fn(u128, u128, WireId) -> u128,
+    ) -> Vec<u128> {
+        let mut labels = vec![0; self.header().num_wires() as usize];
+        labels[..input_labels.len()].copy_from_slice(input_labels);
+        let mut tables = tables.iter();
+        let mut next_table = || tables.next().expect("every AND has a garbled table");
+
+        for gate in self.gates() {
+            match gate {
+                Gate::XOR { input_a, input_b, output } =>
+                    labels[output.index()] = labels[input_a.index()] ^ labels[input_b.index()],
+                // the labels of the input and output of an INV are the same, only their meaning is swapped
+                Gate::INV { input, output } | Gate::EQW { input, output } =>
+                    labels[output.index()] = labels[input.index()],
+                Gate::EQ { output, .. } =>
+                    labels[output.index()] = constant_labels[output],
+                Gate::AND { input_a, input_b, output } =>
+                    labels[output.index()] = next_table().evaluate_with_hash(labels[input_a.index()], labels[input_b.index()], &hash),
+                Gate::MAND { inputs, outputs } => {
+                    let (inputs_a, inputs_b) = inputs.split_at(outputs.len());
+                    for ((input_a, input_b), output) in inputs_a.iter().zip(inputs_b).zip(outputs) {
+                        labels[output.index()] = next_table().evaluate_with_hash(labels[input_a.index()], labels[input_b.index()], &hash);
+                    }
+                }
+            }
+        }
+
+        let num_output_wires: u32 = self.header().output_wires().iter().sum();
+        labels.split_off((self.header().num_wires() - num_output_wires) as usize)
+    }
+}
+
+impl GarbledTable {
+    /// Decrypts the output label of the AND from the row selected by the given input labels
+    pub fn evaluate(&self, label_a: u128, label_b: u128) -> u128 {
+        self.evaluate_with_hash(label_a, label_b, hash_labels)
+    }
+
+    /// Decrypts the output label of the AND like `evaluate`, using the hash the table was garbled with
+    pub fn evaluate_with_hash(&self, label_a: u128, label_b: u128, hash: impl Fn(u128, u128, WireId) -> u128) -> u128 {
+        let row = 2 * (label_a & 1) as usize + (label_b & 1) as usize;
+        self.rows[row] ^ hash(label_a, label_b, self.output)
+    }
+}
+
+/// Samples a new zero-label for the output of an AND and computes its garbled table
+fn garble_and(
+    zero_labels: &mut [u128],
+    delta: u128,
+    sampler: &mut LabelSampler,
+    hash: impl Fn(u128, u128, WireId) -> u128,
+    input_a: WireId,
+    input_b: WireId,
+    output: WireId,
+) -> GarbledTable {
+    zero_labels[output.index()] = sampler.sample();
+    let label = |wire: WireId, bit: bool| zero_labels[wire.index()] ^ if bit { delta } else { 0 };
+
+    let mut rows = [0; 4];
+    for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+        let (label_a, label_b) = (label(input_a, a), label(input_b, b));
+        let row = 2 * (label_a & 1) as usize + (label_b & 1) as usize;
+        rows[row] = hash(label_a, label_b, output) ^ label(output, a & b);
+    }
+    GarbledTable { output, rows }
+}
+
+/// Hashes two input labels and the output wire (as a tweak) into a key for a row of a garbled table
+fn hash_labels(label_a: u128, label_b: u128, tweak: WireId) -> u128 {
+    let half = |part: u8| {
+        let mut hasher = DefaultHasher::new();
+        (label_a, label_b, tweak, part).hash(&mut hasher);
+        hasher.finish() as u128
+    };
+    half(0) << 64 | half(1)
+}
+
+/// Samples random labels by hashing a counter with a randomly keyed hasher
+struct LabelSampler {
+    state: RandomState,
+    counter: u64,
+}
+
+impl LabelSampler {
+    fn new() -> Self {
+        LabelSampler { state: RandomState::new(), counter: 0 }
+    }
+
+    fn sample(&mut self) -> u128 {
+        let mut half = || {
+            self.counter += 1;
+            self.state.hash_one(self.counter) as u128
+        };
+        half() << 64 | half()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit::{Circuit, CircuitFormat, WireId};
+
+    #[test]
+    fn test_free_xor_labels() {
+        let input = "3 6\n\
+            2 1 1\n\
+            1 1\n\
+            \n\
+            2 1 0 1 2 XOR\n\
+            2 1 2 1 3 XOR\n\
+            2 1 3 0 5 XOR";
+        let circuit = Circuit::parse_with_format(input, CircuitFormat::Fashion).unwrap();
+        let garbled = circuit.garble(0x1234_5678_9abc_def0);
+        let labels = &garbled.labels;
+
+        assert_eq!(labels.delta() & 1, 1);
+        assert!(garbled.tables.is_empty());
+        for (input_a, input_b, output) in [(0, 1, 2), (2, 1, 3), (3, 0, 5)].map(|(a, b, out)| (WireId(a), WireId(b), WireId(out))) {
+            assert_ne!(labels.zero(output), labels.one(output));
+            for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+                assert_eq!(labels.label(input_a, a) ^ labels.label(input_b, b), labels.label(output, a ^ b));
+            }
+        }
+    }
+
+    #[test]
+    fn test_garbled_and() {
+        let circuit = Circuit::parse_with_format("1 3\n2 1 1\n1 1\n2 1 0 1 2 AND", CircuitFormat::Fashion).unwrap();
+        // fixed (insecure) hash, s.t. the garbled table only depends on the labels
+        let hash = |a: u128, b: u128, tweak: WireId| a.rotate_left(7) ^ b.rotate_left(42) ^ tweak.0 as u128;
+        let garbled = circuit.garble_with_hash(0xdead_beef << 64, hash);
+        let labels = &garbled.labels;
+
+        assert_eq!(garbled.tables.len(), 1);
+        assert_eq!(garbled.tables[0].output, WireId(2));
+        for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+            let (label_a, label_b) = (labels.label(WireId(0), a), labels.label(WireId(1), b));
+            assert_eq!(garbled.tables[0].evaluate_with_hash(label_a, label_b, hash), labels.label(WireId(2), a & b));
+            assert_eq!(
+                circuit.evaluate_garbled_with_hash(&garbled.tables, &garbled.constant_labels, &[label_a, label_b], hash),
+                vec![labels.label(WireId(2), a & b)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_garbled_tiny_circuit() {
+        let circuit = Circuit::from_file_with_format("res/tiny.txt", CircuitFormat::Fashion).unwrap();
+        let garbled = circuit.garble(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef);
+        let labels = &garbled.labels;
+
+        for bits in 0..16 {
+            let inputs: Vec<bool> = (0..4).map(|i| bits >> i & 1 == 1).collect();
+            let input_labels: Vec<u128> = inputs.iter().enumerate().map(|(wire, &bit)| labels.label(WireId(wire as u32), bit)).collect();
+            let output_labels = circuit.evaluate_garbled(&garbled.tables, &garbled.constant_labels, &input_labels);
+
+            assert_eq!(output_labels, vec![labels.label(WireId(7), !inputs.iter().all(|&bit| bit))]);
+        }
+    }
+}
@@ -0,0 +1,11 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod circuit;
+pub mod circuits;
+#[cfg(feature = "std")]
+pub mod party;
+pub mod mul_triple;
+#[cfg(feature = "std")]
+pub mod garble;
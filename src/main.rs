@@ -1,13 +1,10 @@
 use std::path::PathBuf;
 
-pub mod circuit;
-pub mod party;
-pub mod mul_triple;
-
 /// For argument parsing, my favorite crate is clap https://docs.rs/clap/latest/clap/
 /// Especially its derive feature makes declarative argument parsing really easy.
 /// You can add clap as a dependency with the derive feature and annotate this struct
 /// and add the necessary fields.
+#[allow(dead_code)]
 struct Args {
     arg: PathBuf
 }
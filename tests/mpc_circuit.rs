@@ -0,0 +1,39 @@
+use std::process::Command;
+
+fn mpc_circuit(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_mpc-circuit"))
+        .args(args)
+        .output()
+        .expect("the binary can be executed")
+}
+
+#[test]
+fn test_summary() {
+    let output = mpc_circuit(&["res/tiny.txt"]);
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "gates: 4 (XOR: 0, AND: 3, INV: 1, MAND: 0)\n\
+         wires: 8\n\
+         AND-depth: 2\n\
+         input ports: 4 (1 1 1 1)\n\
+         output ports: 1 (1)\n"
+    );
+}
+
+#[test]
+fn test_dot() {
+    let output = mpc_circuit(&["--dot", "res/tiny.txt"]);
+
+    assert!(output.status.success());
+    let dot = String::from_utf8(output.stdout).unwrap();
+    assert!(dot.starts_with("digraph circuit {\n"));
+    assert!(dot.contains("g3 -> out0 [label=\"7\"];"));
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(mpc_circuit(&[]).status.code(), Some(2));
+    assert_eq!(mpc_circuit(&["res/missing.txt"]).status.code(), Some(1));
+}
@@ -0,0 +1,15 @@
+//! Exercises the parsing path that remains without the `std` feature. Run it with
+//! `cargo test --no-default-features`; the circuit is embedded, as reading files needs std.
+
+use mpc_in_rust::circuit::{Circuit, CircuitFormat};
+
+#[test]
+fn test_parse_embedded_tiny_circuit() {
+    let circuit = Circuit::parse_with_format(include_str!("../res/tiny.txt"), CircuitFormat::Fashion).unwrap();
+
+    assert_eq!(circuit.gates().len(), 4);
+    assert_eq!(circuit.header().num_wires(), 8);
+    // The tiny circuit computes the negated AND of all four inputs
+    assert_eq!(circuit.evaluate(&[vec![true], vec![true], vec![true], vec![true]]), vec![vec![false]]);
+    assert_eq!(circuit.evaluate(&[vec![true], vec![false], vec![true], vec![true]]), vec![vec![true]]);
+}
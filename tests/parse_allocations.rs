@@ -0,0 +1,40 @@
+//! Guards against allocations per gate line when parsing, which add up for circuits with millions
+//! of gates. This is a separate test binary, as it counts the allocations of the whole process.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use mpc_in_rust::circuit::{Circuit, CircuitFormat};
+
+/// Allocator counting the number of allocations, like the one of the benchmarks
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+#[test]
+fn test_parse_allocations_independent_of_gates() {
+    let aes = std::fs::read_to_string("res/aes_128.txt").unwrap();
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let circuit = Circuit::parse_with_format(&aes, CircuitFormat::Fashion).unwrap();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    // the header and the Vec of the gates need a few allocations, while the gate lines need none,
+    // as only MAND gates store their wires on the heap
+    assert!(circuit.gates().len() > 30000);
+    assert!(allocations <= 16, "parsing {} gates took {allocations} allocations", circuit.gates().len());
+}